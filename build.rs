@@ -20,7 +20,6 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-
 use std::collections::HashMap;
 
 use chrono::Local;
@@ -31,19 +30,19 @@ fn main() {
     let git_commit: String = get_git_commit();
     let os_param: HashMap<String, String> = compile_by_os();
 
-    println!("-------- {} --------","start cargo set environment variables ");
+    println!("-------- start cargo set environment variables --------");
     println!("cargo:rustc-env=GIT_COMMIT={}", git_commit);
     println!("cargo:rustc-env=BUILD_DATE={}", build_date);
     for (key, value) in os_param {
         println!("cargo:rustc-env={}={}", key, value);
     }
-    println!("-------- {} --------","finish cargo set environment variables");
+    println!("-------- finish cargo set environment variables --------");
 }
 
 fn compile_by_os() -> HashMap<String, String> {
     let mut os_param: HashMap<String, String> = HashMap::<String,String>::new();
     if cfg!(target_os = "windows") {
-        println!("-------- {} --------","detect windows platform");
+        println!("-------- detect windows platform --------");
 
         // Persist run file under per-user directory so user_info/token survive reboot.
         // Kept as an environment-variable template and resolved at runtime.
@@ -51,16 +50,25 @@ fn compile_by_os() -> HashMap<String, String> {
             "RUN_FILE_BARK".to_string(),
             "%LOCALAPPDATA%\\bark\\bark.conf".to_string(),
         );
+        // Cached APNs tokens are kept apart from the config, so it can be read-only.
+        os_param.insert(
+            "TOKEN_CACHE_BARK".to_string(),
+            "%LOCALAPPDATA%\\bark\\cache\\token.toml".to_string(),
+        );
     } else if cfg!(target_os = "macos") {
-        println!("-------- {} --------","detect macos platform");
+        println!("-------- detect macos platform --------");
 
         // Persist run file in user's Application Support directory.
         os_param.insert(
             "RUN_FILE_BARK".to_string(),
             "~/Library/Application Support/bark/bark.conf".to_string(),
         );
+        os_param.insert(
+            "TOKEN_CACHE_BARK".to_string(),
+            "~/Library/Caches/bark/token.toml".to_string(),
+        );
     } else if cfg!(target_os = "linux") {
-        println!("-------- {} --------","detect linux platform");
+        println!("-------- detect linux platform --------");
 
         // /run is typically tmpfs and will be wiped after reboot.
         // Persist run file in XDG-like user share directory.
@@ -68,6 +76,11 @@ fn compile_by_os() -> HashMap<String, String> {
             "RUN_FILE_BARK".to_string(),
             "~/.local/share/bark/bark.conf".to_string(),
         );
+        // `$XDG_CACHE_HOME` wins over it at runtime.
+        os_param.insert(
+            "TOKEN_CACHE_BARK".to_string(),
+            "~/.cache/bark/token.toml".to_string(),
+        );
     } else {
         panic!("unsupported platform");
     }
@@ -78,16 +91,11 @@ fn compile_by_os() -> HashMap<String, String> {
 fn get_git_commit() -> String {
     let mut git_commit = "unknown".to_string();
     if let Ok(repository) = Repository::open(".") {
-        let _ = repository.head().and_then(|head| {
-            head.target().and_then(|oid| {
-                let _ = repository.find_commit(oid).and_then(|c|{
-                    git_commit = c.id().to_string()[..7].to_string();
-                    Ok(())
-                });
-                Some(oid)
-            });
-        Ok(())
-        });
+        if let Some(oid) = repository.head().ok().and_then(|head| head.target()) {
+            if let Ok(c) = repository.find_commit(oid) {
+                git_commit = c.id().to_string()[..7].to_string();
+            }
+        }
     }
     git_commit
 }
\ No newline at end of file
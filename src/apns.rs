@@ -0,0 +1,242 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use std::collections::HashMap;
+
+use bark_dev::msg::Msg;
+use openssl::ecdsa::EcdsaSig;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::json;
+
+use crate::module::apns_config::Credential;
+
+/// length of r and s of an ES256 signature
+const ES256_INT_LEN: i32 = 32;
+
+/// bundle id of the Bark app, the topic of a push made with its built-in credential
+pub const BARK_APP_TOPIC: &str = "me.fin.bark";
+
+/// what APNs answered for one device
+///
+/// `status` is None when the request did not reach APNs, `reason` then holds the transport error
+pub struct ApnsResponse {
+    pub status: Option<u16>,
+    pub reason: Option<String>,
+    pub apns_id: Option<String>,
+}
+
+impl ApnsResponse {
+    pub fn is_success(&self) -> bool {
+        self.status == Some(200)
+    }
+
+    /// whether sending again may succeed
+    ///
+    /// network errors, throttling (429) and server errors (5xx) are temporary,
+    /// everything else like BadDeviceToken (400) or Unregistered (410) fails the same way again
+    pub fn is_retriable(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(429) => true,
+            Some(status) => status >= 500,
+        }
+    }
+}
+
+/// apns request headers of `msg`, in the order they are sent
+pub fn headers(msg: &Msg, topic: &str) -> Vec<(&'static str, String)> {
+    let mut headers: Vec<(&str, String)> = Vec::new();
+    // a delete msg is a silent background push, which APNs only accepts with priority 5
+    if msg.is_deleted() && msg.get_id().is_some() {
+        headers.push(("apns-push-type", "background".to_string()));
+        headers.push(("apns-priority", "5".to_string()));
+    } else {
+        headers.push(("apns-push-type", "alert".to_string()));
+        headers.push(("apns-priority", "10".to_string()));
+    }
+    if let Some(msg_id) = msg.get_id() {
+        headers.push(("apns-collapse-id", msg_id));
+    }
+    headers.push(("apns-topic", topic.to_string()));
+    headers
+}
+
+/// provider token of `credential` issued at `iat`, a JWT signed with ES256
+pub fn sign_token(credential: &Credential, iat: u64) -> Result<String, String> {
+    let header: String = base64_url(json!({ "alg": "ES256", "kid": credential.get_key_id() }).to_string().as_bytes());
+    let claims: String = base64_url(json!({ "iss": credential.get_team_id(), "iat": iat }).to_string().as_bytes());
+    let signing_input: String = format!("{}.{}", header, claims);
+
+    let sign_failed = |e: openssl::error::ErrorStack| format!("sign apns token failed: {}", e);
+    let sig: EcdsaSig = EcdsaSig::sign(&openssl::sha::sha256(signing_input.as_bytes()), credential.get_key()).map_err(sign_failed)?;
+    // a JWT takes r and s side by side, not the DER openssl gives
+    let mut signature: Vec<u8> = sig.r().to_vec_padded(ES256_INT_LEN).map_err(sign_failed)?;
+    signature.extend(sig.s().to_vec_padded(ES256_INT_LEN).map_err(sign_failed)?);
+    Ok(format!("{}.{}", signing_input, base64_url(&signature)))
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    openssl::base64::encode_block(bytes)
+        .replace('+', "-")
+        .replace('/', "_")
+        .replace('=', "")
+}
+
+/// send the payload of msg to devices on `host` one by one, `msg` tells the headers
+///
+/// return the response of every device
+pub fn send(msg: &Msg, payload: &str, host: &str, topic: &str, token: &str, devices: &[String]) -> HashMap<String, ApnsResponse> {
+    match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt.block_on(async_send(msg, payload, host, topic, token, devices)),
+        Err(e) => devices.iter()
+            .map(|device| (device.to_string(), ApnsResponse { status: None, reason: Some(e.to_string()), apns_id: None }))
+            .collect(),
+    }
+}
+
+async fn async_send(msg: &Msg, payload: &str, host: &str, topic: &str, token: &str, devices: &[String]) -> HashMap<String, ApnsResponse> {
+    let mut results: HashMap<String, ApnsResponse> = HashMap::new();
+
+    let client: reqwest::Client = match reqwest::ClientBuilder::new().http2_prior_knowledge().build() {
+        Ok(client) => client,
+        Err(e) => {
+            for device in devices {
+                results.insert(device.to_string(), ApnsResponse { status: None, reason: Some(e.to_string()), apns_id: None });
+            }
+            return results;
+        }
+    };
+
+    let mut header_map: HeaderMap = HeaderMap::new();
+    for (name, value) in headers(msg, topic) {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            header_map.insert(name, value);
+        }
+    }
+
+    let body: String = payload.to_string();
+    for device in devices {
+        if results.contains_key(device) {
+            continue;
+        }
+        let resp = client
+            .post(format!("https://{host}/3/device/{device}", host = host, device = device))
+            .bearer_auth(token)
+            .headers(header_map.clone())
+            .body(body.clone())
+            .send().await;
+
+        let response: ApnsResponse = match resp {
+            Ok(resp) => {
+                let status: u16 = resp.status().as_u16();
+                let apns_id: Option<String> = resp.headers().get("apns-id")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string());
+                // error body looks like {"reason":"BadDeviceToken"}
+                let reason: Option<String> = if resp.status().is_success() {
+                    None
+                } else {
+                    resp.text().await.ok()
+                        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+                        .and_then(|v| v.get("reason").and_then(|r| r.as_str()).map(|r| r.to_string()))
+                };
+                ApnsResponse { status: Some(status), reason, apns_id }
+            },
+            // the url carries the device token, keep it out of the reason
+            Err(e) => ApnsResponse { status: None, reason: Some(e.without_url().to_string()), apns_id: None },
+        };
+        results.insert(device.to_string(), response);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: Option<u16>, reason: &str) -> ApnsResponse {
+        ApnsResponse { status, reason: Some(reason.to_string()), apns_id: None }
+    }
+
+    #[test]
+    fn test_is_retriable() {
+        assert!(response(None, "error sending request").is_retriable());
+        assert!(response(Some(429), "TooManyRequests").is_retriable());
+        assert!(response(Some(500), "InternalServerError").is_retriable());
+        assert!(response(Some(503), "ServiceUnavailable").is_retriable());
+
+        assert!(!response(Some(400), "BadDeviceToken").is_retriable());
+        assert!(!response(Some(403), "InvalidProviderToken").is_retriable());
+        assert!(!response(Some(410), "Unregistered").is_retriable());
+        assert!(!response(Some(413), "PayloadTooLarge").is_retriable());
+    }
+
+    #[test]
+    fn test_sign_token() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("bark_apns_sign_test.p8");
+        let group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let key = openssl::ec::EcKey::generate(&group).unwrap();
+        std::fs::write(&path, openssl::pkey::PKey::from_ec_key(key.clone()).unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let config: crate::module::apns_config::ApnsConfig = toml::from_str(&format!(
+            "key_file = {:?}\nkey_id = \"KEY\"\nteam_id = \"TEAM\"\ntopic = \"com.example.bark\"\n", path.to_str().unwrap()
+        )).unwrap();
+        let credential: Credential = config.credential().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let token: String = sign_token(&credential, 1700000000).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        assert_eq!(3, parts.len());
+        let decode = |part: &str| {
+            let mut part: String = part.replace('-', "+").replace('_', "/");
+            while !part.len().is_multiple_of(4) {
+                part.push('=');
+            }
+            openssl::base64::decode_block(&part).unwrap()
+        };
+        assert_eq!(b"{\"alg\":\"ES256\",\"kid\":\"KEY\"}".to_vec(), decode(parts[0]));
+        assert_eq!(b"{\"iat\":1700000000,\"iss\":\"TEAM\"}".to_vec(), decode(parts[1]));
+
+        let signature: Vec<u8> = decode(parts[2]);
+        assert_eq!(64, signature.len());
+        let r = openssl::bn::BigNum::from_slice(&signature[..32]).unwrap();
+        let s = openssl::bn::BigNum::from_slice(&signature[32..]).unwrap();
+        let sig: EcdsaSig = EcdsaSig::from_private_components(r, s).unwrap();
+        let digest = openssl::sha::sha256(format!("{}.{}", parts[0], parts[1]).as_bytes());
+        assert!(sig.verify(&digest, &key).unwrap());
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut msg = Msg::new("title", "body");
+        assert_eq!(
+            vec![("apns-push-type", "alert".to_string()), ("apns-priority", "10".to_string()), ("apns-topic", BARK_APP_TOPIC.to_string())],
+            headers(&msg, BARK_APP_TOPIC)
+        );
+
+        msg.set_id("id").set_deleted();
+        assert_eq!(
+            vec![("apns-push-type", "background".to_string()), ("apns-priority", "5".to_string()), ("apns-collapse-id", "id".to_string()), ("apns-topic", BARK_APP_TOPIC.to_string())],
+            headers(&msg, BARK_APP_TOPIC)
+        );
+    }
+}
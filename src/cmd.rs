@@ -21,35 +21,58 @@
 // SOFTWARE.
 
 
-use std::process::exit;
+use std::{ffi::OsString, fs::File, process::exit, time::Duration};
 
 use bark_dev::msg::{self, Msg};
-use clap::{ArgMatches, Command, CommandFactory, FromArgMatches};
+use chrono::{DateTime, Local, TimeDelta};
+use clap::{parser::ValueSource, ArgMatches, Command, CommandFactory, FromArgMatches};
+use serde::{Deserialize, Serialize};
 
-use crate::{config, module::{run_file::RunFile, user_info::UserInfo}, util::file_utils};
+use crate::{config, config_file, crypto, module::{alertmanager::AlertResolved, apns_config::ApnsConfig, encryption::{Encryption, EncryptionMode, EncryptionType, IvPolicy, KeyFormat}, group_info::GroupInfo, mapping::Mapping, job_queue::JobQueue, repeat::{CatchUp, Repeat}, retry_policy::{self, RetryPolicy}, run_file::RunFile, template::Template, user_info::UserInfo}, util::{crypto_utils, file_utils, msg_utils, template_utils, time_utils}};
 
 
-#[derive(clap::Parser, Debug)]
+/// title of a msg sent without -t
+pub const DEFAULT_TITLE: &str = "Notification";
+
+// also stored as the options of a scheduled job, options that only matter to this invocation are skipped
+#[allow(clippy::upper_case_acronyms)]
+#[derive(clap::Parser, Serialize, Deserialize, Debug, Clone)]
 #[clap(
     author = env!("CARGO_PKG_AUTHORS"),
     version = env!("CARGO_PKG_VERSION"),
     about = "cli msg sender",
     long_about = env!("CARGO_PKG_DESCRIPTION"),
-    override_usage = env!("CARGO_PKG_NAME").to_owned() + " [OPTIONS] -m <MSG> -r <RECEIVER>..."
+    override_usage = env!("CARGO_PKG_NAME").to_owned() + " [OPTIONS] [-m <MSG>|--msg-file <MSG_FILE>] -r <RECEIVER>..."
 )]
 pub struct CMD {
     /// title
-    #[arg(short, long, required = false, default_value = "Notification")]
+    #[arg(short, long, required = false, default_value = DEFAULT_TITLE)]
     pub title: String,
     /// msg content
-    #[arg(short, long)]
-    pub msg: String,
+    /// pass "-" to read it from stdin
+    /// content exceeding the APNs payload limit will be truncated
+    #[arg(short, long, required = false, conflicts_with = "msg_file", verbatim_doc_comment)]
+    pub msg: Option<String>,
+    /// read msg content from file
+    #[serde(skip)]
+    #[arg(long, required = false)]
+    pub msg_file: Option<String>,
     /// send to whom in format of user1,user2...
     #[arg(short, long, value_delimiter = ',')]
     pub receiver: Vec<String>,
     /// after how many seconds to send, positive number [1..]
+    #[serde(skip)]
     #[arg(short, long, required = false, value_parser = clap::value_parser!(u64).range(1..))]
     pub delay: Option<u64>,
+    /// send at a point in time, like "2026-10-19 09:00", "2026-10-19T09:00:00+08:00", "18:30", "tomorrow 9am"
+    /// times without an offset are in the local timezone, past times are refused
+    #[serde(skip)]
+    #[arg(long, required = false, conflicts_with = "delay", value_parser = parse_at, verbatim_doc_comment)]
+    pub at: Option<DateTime<Local>>,
+    /// wait silently for --delay or --at instead of showing a count down
+    #[serde(skip)]
+    #[arg(long, visible_alias = "quiet", required = false)]
+    pub no_countdown: bool,
     /// Push Interruption Level(active, timeSensitive, passive)
     #[arg(short, long, required = false, default_value = "active")]
     pub level: String,
@@ -101,12 +124,25 @@ pub struct CMD {
     /// gcm mode
     #[arg(long, required = false, conflicts_with_all = &["cbc", "ecb"])]
     pub gcm: bool,
-    /// encryption key
-    #[arg(short, long, required = false)]
+    /// encryption key, pass "-" to type it at a hidden prompt
+    /// 16 bytes for aes128, 24 for aes192 and 32 for aes256
+    #[serde(skip)]
+    #[arg(short, long, required = false, conflicts_with_all = &["key_file", "key_env"], verbatim_doc_comment)]
     pub key: Option<String>,
-    /// iv
+    /// read the encryption key from a file, a trailing newline is ignored
+    #[arg(long, required = false, conflicts_with_all = &["key", "key_env"])]
+    pub key_file: Option<String>,
+    /// read the encryption key from the environment variable KEY_ENV
+    #[arg(long, required = false, conflicts_with_all = &["key", "key_file"])]
+    pub key_env: Option<String>,
+    /// how the key is written
+    #[serde(default)]
+    #[arg(long, required = false, value_enum, default_value = "text")]
+    pub key_format: KeyFormat,
+    /// iv, 16 bytes for cbc and 12 for gcm, ecb takes none
     /// if not passed, it will be randomly generated
-    #[arg(long, required = false)]
+    #[serde(skip)]
+    #[arg(long, required = false, verbatim_doc_comment)]
     pub iv: Option<String>,
     /// message id
     #[arg(long, required = false)]
@@ -114,20 +150,77 @@ pub struct CMD {
     /// delete msg
     #[arg(long, required = false, default_value = "false", requires_if("true", "id"))]
     pub delete: bool,
+    /// how many times to resend to devices failed with a retriable error
+    /// network errors, 429 and 5xx are retried, errors like BadDeviceToken are not
+    #[arg(long, required = false, default_value = "0", verbatim_doc_comment)]
+    pub retries: u32,
+    /// seconds to wait before the first retry [0..3600], doubled for every following retry up to an hour
+    #[arg(long, required = false, default_value = "1")]
+    pub retry_backoff: f64,
+    /// randomly lengthen every wait by up to this ratio of it [0..1]
+    #[arg(long, required = false, default_value = "0.2")]
+    pub retry_jitter: f64,
+    /// stop retrying once this many seconds passed since the first attempt
+    #[arg(long, required = false)]
+    pub retry_max_time: Option<u64>,
+    #[command(flatten)]
+    #[serde(default)]
+    pub apns: ApnsConfig,
+    /// build the msg from a template saved by `bark template add`
+    /// title, msg, level, sound, group, icon and url passed on the command line win over the template
+    #[serde(skip)]
+    #[arg(long, required = false, verbatim_doc_comment)]
+    pub template: Option<String>,
+    /// value of a {{KEY}} placeholder of the template like "env=prod" ["version=1.2" ...]
+    /// built-in: {{hostname}}, {{now}}, {{date}}, {{user}} and {{env.NAME}} for the environment variable NAME
+    #[serde(skip)]
+    #[arg(long, required = false, requires = "template", value_parser = parse_var, verbatim_doc_comment)]
+    pub var: Vec<(String, String)>,
+    /// print the request that would be sent (headers, payload and receivers) instead of sending it
+    #[serde(skip)]
+    #[arg(long, required = false)]
+    pub dry_run: bool,
+    /// how to report the send result
+    /// text: print failed receivers to stderr
+    /// json: print the result of every receiver to stdout
+    /// the exit code is 0 when all delivered, 1 all failed, 2 invalid input, 3 partial failure
+    #[serde(skip)]
+    #[arg(long, required = false, value_enum, default_value = "text", verbatim_doc_comment)]
+    pub output: OutputFormat,
     /// config file in toml format
-    #[arg(short, long, required = false, default_value = config::RUN_FILE_BARK)]
+    /// precedence: --config > env RUN_FILE_BARK > default
+    #[serde(skip)]
+    #[arg(short, long, required = false, env = "RUN_FILE_BARK", default_value = config::RUN_FILE_BARK, verbatim_doc_comment)]
     pub config: String,
+    /// take the defaults of options from the [profile.<PROFILE>] table of the config file
+    /// options passed on the command line win over the profile
+    #[serde(skip)]
+    #[arg(long, required = false, env = "BARK_PROFILE", verbatim_doc_comment)]
+    pub profile: Option<String>,
+    /// refuse to use a config file which others than its owner can access, instead of warning about it
+    #[serde(skip)]
+    #[arg(long, required = false, verbatim_doc_comment)]
+    pub strict: bool,
 
     #[command(subcommand)]
+    #[serde(skip)]
     pub command: Option<CMDCommand>,
 }
 
-#[derive(clap::Subcommand, Debug)]
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
 pub enum CMDCommand {
     #[clap(about = "alias of device token")]
     User {
         /// add user like "alias:device_token" ["alias1:device_token1" ...]
-        #[arg(long, required = false, conflicts_with_all = &["del", "get"], num_args = 1..)]
+        /// with --aes*, a mode and -k, msgs to the added users are encrypted with them instead of the ones passed when sending
+        #[arg(long, required = false, conflicts_with_all = &["del", "get"], num_args = 1.., verbatim_doc_comment)]
         add: Vec<UserInfo>,
         /// delete user like "alias1" ["alias2" ...] 
         #[arg(long, required = false, conflicts_with_all = &["add", "get"], num_args = 1..)]
@@ -136,8 +229,303 @@ pub enum CMDCommand {
         /// if not passed, all users will be displayed
         #[arg(long, required = false, conflicts_with_all = &["add", "del"], verbatim_doc_comment, num_args = 0..=1)]
         get: String,
+        #[command(flatten)]
+        encryption: EncryptionArgs,
+    },
+    #[clap(about = "group of receivers")]
+    Group {
+        /// add group like "name:member1,member2" ["name2:member3" ...]
+        /// member can be an alias, a device token or another group
+        #[arg(long, required = false, conflicts_with_all = &["del", "get"], num_args = 1.., verbatim_doc_comment)]
+        add: Vec<GroupInfo>,
+        /// delete group like "name1" ["name2" ...]
+        #[arg(long, required = false, conflicts_with_all = &["add", "get"], num_args = 1..)]
+        del: Vec<String>,
+        /// get group like "name"
+        /// if not passed, all groups will be displayed
+        #[arg(long, required = false, conflicts_with_all = &["add", "del"], verbatim_doc_comment, num_args = 0..=1)]
+        get: String,
+    },
+    #[clap(about = "receivers of webhook events by repository or user name")]
+    Mapping {
+        /// add mapping like "owner/repo:receiver1,receiver2" ["octocat:alias" ...]
+        /// a receiver can be an alias, a device token or a group
+        #[arg(long, required = false, conflicts_with_all = &["del", "get"], num_args = 1.., verbatim_doc_comment)]
+        add: Vec<Mapping>,
+        /// delete mapping like "owner/repo" ["octocat" ...]
+        #[arg(long, required = false, conflicts_with_all = &["add", "get"], num_args = 1..)]
+        del: Vec<String>,
+        /// get mapping like "owner/repo"
+        /// if not passed, all mappings will be displayed
+        #[arg(long, required = false, conflicts_with_all = &["add", "del"], verbatim_doc_comment, num_args = 0..=1)]
+        get: String,
+    },
+    #[clap(
+        about = "run a command and notify when it finishes",
+        long_about = "run a command and notify when it finishes\n\
+            the title tells the command line and how it ended, after the title passed by -t if any\n\
+            the msg tells the exit code, elapsed time and the last lines of output\n\
+            receivers and other msg options are passed before the subcommand, bark exits with the exit code of the command",
+        override_usage = env!("CARGO_PKG_NAME").to_owned() + " [OPTIONS] -r <RECEIVER>... run [RUN_OPTIONS] [--] <COMMAND>..."
+    )]
+    Run {
+        /// only notify when the command fails
+        #[arg(long, required = false)]
+        only_on_failure: bool,
+        /// Push Interruption Level used when the command fails, e.g. timeSensitive
+        /// if not passed, the same level as a success
+        #[arg(long, required = false, verbatim_doc_comment)]
+        failure_level: Option<String>,
+        /// how many lines at the end of the output to put into the msg
+        #[arg(long, required = false, default_value = "10")]
+        tail: usize,
+        /// command to run with its arguments
+        #[arg(required = true, num_args = 1.., trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    #[clap(
+        about = "queue msgs to be sent later by `bark daemon`",
+        long_about = "queue msgs to be sent later by `bark daemon`\n\
+            the queue is kept next to the config file, so a scheduled msg survives a closed terminal or a reboot",
+    )]
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+    #[clap(
+        about = "send scheduled msgs when they are due",
+        long_about = "send scheduled msgs when they are due\n\
+            keeps running, jobs due while it was not running are sent once it starts\n\
+            the result of every job is written back to the queue",
+    )]
+    Daemon,
+    #[clap(
+        about = "serve the Bark server push API as a local gateway",
+        long_about = "serve the Bark server push API as a local gateway\n\
+            GET or POST /:key/:body, /:key/:title/:body, /:key/:title/:subtitle/:body and POST /push with json\n\
+            POST /alertmanager[/:key] takes an Alertmanager webhook and sends every alert in it\n\
+            POST /github[/:key] and /gitlab[/:key] take webhooks about failed CI and review requests\n\
+            the key is an alias, a group or a device token, msg options before the subcommand are the defaults of every push",
+        override_usage = env!("CARGO_PKG_NAME").to_owned() + " [OPTIONS] serve [SERVE_OPTIONS]"
+    )]
+    Serve(ServeArgs),
+    #[clap(about = "msg templates with {{var}} placeholders, used by --template")]
+    Template {
+        #[command(subcommand)]
+        command: TemplateCommand,
+    },
+    #[clap(about = "generate keys, encrypt and decrypt like the sender to check the settings of the Bark app")]
+    Crypto {
+        #[command(subcommand)]
+        command: CryptoCommand,
+    },
+    #[clap(about = "inspect, check and repair the config file")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+/// aes options of `bark user --add` and `bark crypto`
+#[derive(clap::Args, Debug, Clone)]
+pub struct EncryptionArgs {
+    /// aes128
+    #[arg(long, required = false, conflicts_with_all = &["aes192", "aes256"])]
+    pub aes128: bool,
+    /// aes192
+    #[arg(long, required = false, conflicts_with_all = &["aes128", "aes256"])]
+    pub aes192: bool,
+    /// aes256
+    #[arg(long, required = false, conflicts_with_all = &["aes128", "aes192"])]
+    pub aes256: bool,
+    /// cbc mode
+    #[arg(long, required = false, conflicts_with_all = &["ecb", "gcm"])]
+    pub cbc: bool,
+    /// ecb mode
+    #[arg(long, required = false, conflicts_with_all = &["cbc", "gcm"])]
+    pub ecb: bool,
+    /// gcm mode
+    #[arg(long, required = false, conflicts_with_all = &["cbc", "ecb"])]
+    pub gcm: bool,
+    /// encryption key, pass "-" to type it at a hidden prompt
+    /// 16 bytes for aes128, 24 for aes192 and 32 for aes256
+    #[arg(short, long, required = false, conflicts_with_all = &["key_file", "key_env"], verbatim_doc_comment)]
+    pub key: Option<String>,
+    /// read the encryption key from a file, a trailing newline is ignored
+    #[arg(long, required = false, conflicts_with_all = &["key", "key_env"])]
+    pub key_file: Option<String>,
+    /// read the encryption key from the environment variable KEY_ENV
+    #[arg(long, required = false, conflicts_with_all = &["key", "key_file"])]
+    pub key_env: Option<String>,
+    /// how the key is written
+    #[arg(long, required = false, value_enum, default_value = "text")]
+    pub key_format: KeyFormat,
+    /// iv, 16 bytes for cbc and 12 for gcm, ecb takes none
+    /// if not passed, a random one is generated to encrypt
+    #[arg(long, required = false, verbatim_doc_comment)]
+    pub iv: Option<String>,
+}
+
+impl EncryptionArgs {
+    pub fn is_empty(&self) -> bool {
+        self.enc_type().is_none() && self.mode().is_none() && self.key.is_none() && self.key_file.is_none() && self.key_env.is_none() && self.iv.is_none()
+    }
+
+    /// the key passed by `--key`, `--key-file` or `--key-env`
+    pub fn key(&self) -> Result<Option<String>, String> {
+        crypto_utils::read_key(self.key.as_deref(), self.key_file.as_deref(), self.key_env.as_deref())
+    }
+
+    pub fn enc_type(&self) -> Option<EncryptionType> {
+        encryption_type(self.aes128, self.aes192, self.aes256)
+    }
+
+    pub fn mode(&self) -> Option<EncryptionMode> {
+        encryption_mode(self.cbc, self.ecb, self.gcm)
+    }
+
+    /// None if no encryption option is passed, an error if some are missing
+    pub fn to_encryption(&self) -> Result<Option<Encryption>, String> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        match (self.enc_type(), self.mode(), self.key()?) {
+            (Some(enc_type), Some(mode), Some(key)) => {
+                let iv: IvPolicy = self.iv.clone().map(IvPolicy::Fixed).unwrap_or_default();
+                let encryption: Encryption = Encryption::new(enc_type, mode, &key, self.key_format, iv);
+                encryption.validate()?;
+                Ok(Some(encryption))
+            },
+            _ => Err("aes encryption type, mode and key are all required".to_string()),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// address to listen on, anyone who can reach it can push to every alias
+    #[arg(long, required = false, default_value = "127.0.0.1:8080")]
+    pub listen: String,
+    /// labels of an alert whose values are tried as alias or group, when the url has no key
+    /// "receiver" falls back to the receiver of the webhook, -r is used when none matches
+    #[arg(long, required = false, value_delimiter = ',', default_value = "team,receiver", verbatim_doc_comment)]
+    pub alert_labels: Vec<String>,
+    /// what a resolved alert does to the notification of the firing one
+    #[arg(long, required = false, value_enum, default_value = "replace")]
+    pub alert_resolved: AlertResolved,
+    /// secret of the GitHub webhook, its deliveries are checked against the HMAC signature
+    /// /github is refused without it
+    #[arg(long, required = false, env = "BARK_GITHUB_SECRET", hide_env_values = true, verbatim_doc_comment)]
+    pub github_secret: Option<String>,
+    /// secret token of the GitLab webhook
+    /// /gitlab is refused without it
+    #[arg(long, required = false, env = "BARK_GITLAB_TOKEN", hide_env_values = true, verbatim_doc_comment)]
+    pub gitlab_token: Option<String>,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum ScheduleCommand {
+    #[clap(
+        about = "queue the msg built from the options before the subcommand",
+        long_about = "queue the msg built from the options before the subcommand\n\
+            the msg is sent at --at or after --delay, one of them is required unless it repeats",
+        override_usage = env!("CARGO_PKG_NAME").to_owned() + " [OPTIONS] -r <RECEIVER>... [--at <AT>|--delay <DELAY>] schedule add [--repeat <REPEAT>]"
+    )]
+    Add {
+        /// send again and again, as a cron expression in local time like "55 9 * * mon-fri", "*/15 * * * *", "@daily"
+        /// or an interval like "every 30m", "every 2h", "every 1d"
+        /// a cron job first runs at its next match from --at/--delay or now, an interval job at --at/--delay or after one interval
+        #[arg(long, required = false, verbatim_doc_comment)]
+        repeat: Option<Repeat>,
+        /// what to do with runs of a repeating job missed by more than 2 minutes, e.g. while the machine was asleep
+        /// skip: drop them and wait for the next run
+        /// fire-once: send once for all of them, then go on as scheduled
+        #[arg(long, required = false, value_enum, default_value = "skip", verbatim_doc_comment)]
+        catch_up: CatchUp,
+    },
+    #[clap(about = "list scheduled jobs and their status")]
+    List,
+    #[clap(about = "cancel pending jobs")]
+    Cancel {
+        /// id of the job like 1 [2 ...]
+        #[arg(required = true, num_args = 1..)]
+        id: Vec<u64>,
     },
-    
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum TemplateCommand {
+    #[clap(
+        about = "save a template, fields can contain {{var}} placeholders",
+        override_usage = env!("CARGO_PKG_NAME").to_owned() + " template add <NAME> [-t <TITLE>] [-m <BODY>] [-l <LEVEL>] [-s <SOUND>] [-g <GROUP>] [-i <ICON>] [--url <URL>]"
+    )]
+    Add(Template),
+    #[clap(about = "list templates")]
+    List,
+    #[clap(about = "show the fields of a template")]
+    Show {
+        /// name of the template
+        name: String,
+    },
+    #[clap(about = "delete templates")]
+    Del {
+        /// name of the template like "deploy" ["backup" ...]
+        #[arg(required = true, num_args = 1..)]
+        name: Vec<String>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum CryptoCommand {
+    #[clap(about = "print a random key of the length the type needs, to be set in the Bark app and passed by -k")]
+    #[command(group(clap::ArgGroup::new("type").required(true).args(["aes128", "aes192", "aes256"])))]
+    Genkey {
+        /// 16 characters for aes128
+        #[arg(long, required = false)]
+        aes128: bool,
+        /// 24 characters for aes192
+        #[arg(long, required = false)]
+        aes192: bool,
+        /// 32 characters for aes256
+        #[arg(long, required = false)]
+        aes256: bool,
+    },
+    #[clap(
+        about = "encrypt a plaintext like the sender does, print the iv and the base64 ciphertext as json",
+        override_usage = env!("CARGO_PKG_NAME").to_owned() + " crypto encrypt <--aes128|--aes192|--aes256> <--cbc|--ecb|--gcm> <-k <KEY>|--key-file <KEY_FILE>|--key-env <KEY_ENV>> [--iv <IV>] [-m <MSG>]"
+    )]
+    Encrypt {
+        #[command(flatten)]
+        encryption: EncryptionArgs,
+        /// plaintext like '{"body":"hello"}', the sender encrypts the body this way
+        /// read from stdin if not passed
+        #[arg(short, long, required = false, verbatim_doc_comment)]
+        msg: Option<String>,
+    },
+    #[clap(
+        about = "decrypt a ciphertext to see what the Bark app gets",
+        override_usage = env!("CARGO_PKG_NAME").to_owned() + " crypto decrypt <--aes128|--aes192|--aes256> <--cbc|--ecb|--gcm> <-k <KEY>|--key-file <KEY_FILE>|--key-env <KEY_ENV>> [--iv <IV>] [-m <MSG>]"
+    )]
+    Decrypt {
+        #[command(flatten)]
+        encryption: EncryptionArgs,
+        /// the base64 ciphertext, or json with "ciphertext" and "iv" like a payload of --dry-run or the output of encrypt
+        /// read from stdin if not passed, --iv wins over the iv in the json
+        #[arg(short, long, required = false, verbatim_doc_comment)]
+        msg: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    #[clap(about = "print the path of the config file in effect")]
+    Path,
+    #[clap(about = "report parse errors, unknown keys, duplicate names and malformed device tokens of the config file")]
+    Check,
+    #[clap(about = "keep what parses of the config file and drop the rest, the old file is kept as <config>.bak")]
+    Repair,
+    #[clap(about = "let only the owner access the config file, the files kept next to it, the token cache and their directories")]
+    FixPerms,
 }
 
 impl CMD {
@@ -151,14 +539,71 @@ impl CMD {
         cmd = cmd.long_version(long_version);
      
         let mut matches: ArgMatches = cmd.get_matches_mut();
-        let config_path: &String = matches.get_one("config").unwrap();
-        let mut run_file: RunFile = file_utils::read_runfile_from_file(config_path);
+        // the one and only config path, every later read or write goes through it
+        let config_path: String = file_utils::resolve_runfile_path(matches.get_one::<String>("config").unwrap());
+        let mut run_file: RunFile = match file_utils::read_runfile_from_file(&config_path) {
+            Ok(run_file) => run_file,
+            // `bark config` looks into a broken file by itself
+            Err(_) if matches.subcommand_name() == Some("config") => RunFile::new_empty(config_path.clone()),
+            Err(e) => cmd.error(clap::error::ErrorKind::Io, format!("{}\nsee what is wrong by `bark config check`, keep what parses by `bark config repair`", e)).exit(),
+        };
+
+        // the options of the profile are parsed as if they were passed before the ones on the command line
+        let mut from_profile: Vec<String> = Vec::new();
+        if let Some(name) = matches.get_one::<String>("profile").cloned() {
+            let Some(profile) = run_file.get_profile(&name) else {
+                cmd.error(clap::error::ErrorKind::InvalidValue, format!("profile {} not found", name)).exit();
+            };
+            let passed = |id: &str| matches!(matches.value_source(id), Some(ValueSource::CommandLine | ValueSource::EnvVariable));
+            let (ids, args): (Vec<String>, Vec<String>) = match profile.to_args(&cmd, passed) {
+                Ok(profile_args) => profile_args,
+                Err(e) => cmd.error(clap::error::ErrorKind::InvalidValue, format!("profile {}: {}", name, e)).exit(),
+            };
+            let mut argv: Vec<OsString> = std::env::args_os().collect();
+            argv.splice(1..1, args.into_iter().map(OsString::from));
+            matches = match cmd.try_get_matches_from_mut(argv) {
+                Ok(matches) => matches,
+                Err(e) => {
+                    eprintln!("invalid option in profile {}", name);
+                    e.exit()
+                },
+            };
+            from_profile = ids;
+        }
+
+        // the config file holds device tokens and keys, only the commands sending with them are checked,
+        // so --strict does not block `bark crypto` and `bark config` can look into the file and fix it
+        let sends: bool = matches!(matches.subcommand_name(), None | Some("run" | "schedule" | "daemon" | "serve"));
+        if let Some(mode) = file_utils::shared_mode(std::path::Path::new(&config_path)).filter(|_| sends) {
+            let msg: String = format!("{} has mode {:04o}, others than its owner can access it, restrict it by `bark config fix-perms`", config_path, mode);
+            if matches.get_flag("strict") {
+                cmd.error(clap::error::ErrorKind::Io, msg).exit();
+            }
+            eprintln!("warning: {}", msg);
+        }
 
         match matches.subcommand() {
             Some(("user", user_matches)) => {
+                let encryption: EncryptionArgs = match EncryptionArgs::from_arg_matches(user_matches) {
+                    Ok(encryption) => encryption,
+                    Err(e) => e.exit(),
+                };
+                if !encryption.is_empty() && !user_matches.contains_id("add") {
+                    cmd.error(clap::error::ErrorKind::ArgumentConflict, "aes options only apply to users added by --add").exit();
+                }
                 if user_matches.contains_id("add") {
-                    let users: Vec<&UserInfo> = user_matches.get_many("add").unwrap().collect();
-                    run_file.add_user_info(users);
+                    let encryption: Option<Encryption> = match encryption.to_encryption() {
+                        Ok(encryption) => encryption,
+                        Err(e) => cmd.error(clap::error::ErrorKind::InvalidValue, format!("{} to encrypt msgs to a user", e)).exit(),
+                    };
+                    let users: Vec<UserInfo> = user_matches.get_many::<UserInfo>("add").unwrap()
+                        .map(|u| {
+                            let mut u: UserInfo = u.clone();
+                            u.set_encryption(encryption.clone());
+                            u
+                        })
+                        .collect();
+                    run_file.add_user_info(users.iter().collect());
                 } else if user_matches.contains_id("del") {
                     let users: Vec<&String> = user_matches.get_many("del").unwrap().collect();
                     run_file.remove_user_info(users);
@@ -185,14 +630,192 @@ impl CMD {
                 }
                 exit(0x00);
             },
+            Some(("mapping", mapping_matches)) => {
+                if mapping_matches.contains_id("add") {
+                    let mappings: Vec<&Mapping> = mapping_matches.get_many("add").unwrap().collect();
+                    run_file.add_mapping(mappings);
+                } else if mapping_matches.contains_id("del") {
+                    let names: Vec<&String> = mapping_matches.get_many("del").unwrap().collect();
+                    run_file.remove_mapping(names);
+                } else if mapping_matches.contains_id("get") {
+                    match mapping_matches.get_one::<String>("get") {
+                        None => {
+                            Mapping::pretty_print(run_file.get_mapping());
+                        },
+                        Some(name) => {
+                            Mapping::pretty_print(run_file.get_mapping_by_name(name).into_iter().collect());
+                        }
+                    }
+                } else {
+                    let _ = cmd.print_help();
+                    exit(0x00);
+                }
+                exit(0x00);
+            },
+            Some(("group", group_matches)) => {
+                if group_matches.contains_id("add") {
+                    let groups: Vec<&GroupInfo> = group_matches.get_many("add").unwrap().collect();
+                    run_file.add_group_info(groups);
+                } else if group_matches.contains_id("del") {
+                    let groups: Vec<&String> = group_matches.get_many("del").unwrap().collect();
+                    run_file.remove_group_info(groups);
+                } else if group_matches.contains_id("get") {
+                    match group_matches.get_one::<String>("get") {
+                        None => {
+                            GroupInfo::pretty_print(run_file.get_group_info());
+                        },
+                        Some(group_name) => {
+                            match run_file.get_group_info_by_name(group_name) {
+                                Some(g) => {
+                                    GroupInfo::pretty_print(vec![g]);
+                                },
+                                None => {
+                                    GroupInfo::pretty_print(vec![]);
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    let _ = cmd.print_help();
+                    exit(0x00);
+                }
+                exit(0x00);
+            },
+            Some(("template", template_matches)) => {
+                match template_matches.subcommand() {
+                    Some(("add", add_matches)) => {
+                        let template: Template = match Template::from_arg_matches(add_matches) {
+                            Ok(template) => template,
+                            Err(e) => e.exit(),
+                        };
+                        if let Err(e) = template.validate() {
+                            cmd.error(clap::error::ErrorKind::InvalidValue, e).exit();
+                        }
+                        run_file.add_template(&template);
+                    },
+                    Some(("list", _)) => Template::pretty_print(run_file.get_template()),
+                    Some(("show", show_matches)) => {
+                        let name: &String = show_matches.get_one("name").unwrap();
+                        match run_file.get_template_by_name(name) {
+                            Some(template) => print!("{}", template),
+                            None => cmd.error(clap::error::ErrorKind::InvalidValue, format!("template {} not found", name)).exit(),
+                        }
+                    },
+                    Some(("del", del_matches)) => {
+                        run_file.remove_template(del_matches.get_many("name").unwrap().collect());
+                    },
+                    _ => {}
+                }
+                exit(0x00);
+            },
+            Some(("crypto", _)) => {
+                let output: Result<String, String> = match CMD::from_arg_matches(&matches) {
+                    Ok(CMD { command: Some(CMDCommand::Crypto { command }), .. }) => crypto::run(&command),
+                    Ok(_) => exit(0x00),
+                    Err(e) => e.exit(),
+                };
+                match output {
+                    Ok(output) => println!("{}", output),
+                    Err(e) => cmd.error(clap::error::ErrorKind::InvalidValue, e).exit(),
+                }
+                exit(0x00);
+            },
+            Some(("config", config_matches)) => {
+                match config_matches.subcommand() {
+                    Some(("path", _)) => println!("{}", config_path),
+                    Some(("check", _)) => match config_file::check(&config_path) {
+                        Ok(problems) if problems.is_empty() => println!("{} is fine", config_path),
+                        Ok(problems) => {
+                            problems.iter().for_each(|problem| println!("{}", problem));
+                            exit(config::EXIT_INVALID_INPUT);
+                        },
+                        Err(e) => cmd.error(clap::error::ErrorKind::Io, e).exit(),
+                    },
+                    Some(("repair", _)) => match config_file::repair(&config_path) {
+                        Ok(dropped) if dropped.is_empty() => println!("{} has nothing to repair", config_path),
+                        Ok(dropped) => {
+                            println!("dropped from {}:", config_path);
+                            dropped.iter().for_each(|dropped| println!("  {}", dropped));
+                            println!("the old file is kept as {}.bak, `bark config check` tells what is left to fix", config_path);
+                        },
+                        Err(e) => cmd.error(clap::error::ErrorKind::Io, e).exit(),
+                    },
+                    Some(("fix-perms", _)) => match file_utils::fix_permissions(&config_path, &file_utils::token_cache_path()) {
+                        Ok(changed) if changed.is_empty() => println!("only the owner can access {}", config_path),
+                        Ok(changed) => changed.iter().for_each(|changed| println!("{}", changed)),
+                        Err(e) => cmd.error(clap::error::ErrorKind::Io, e).exit(),
+                    },
+                    _ => {},
+                }
+                exit(0x00);
+            },
+            Some(("schedule", schedule_matches)) if schedule_matches.subcommand_name() != Some("add") => {
+                let queue_path: String = file_utils::queue_path(&config_path);
+                match schedule_matches.subcommand() {
+                    Some(("list", _)) => match file_utils::read_queue_from_file(&queue_path) {
+                        Ok(queue) => queue.pretty_print(),
+                        Err(e) => cmd.error(clap::error::ErrorKind::Io, e).exit(),
+                    },
+                    Some(("cancel", cancel_matches)) => {
+                        let (_lock, mut queue): (File, JobQueue) = match file_utils::lock_queue(&queue_path) {
+                            Ok(locked) => locked,
+                            Err(e) => cmd.error(clap::error::ErrorKind::Io, e).exit(),
+                        };
+                        for id in cancel_matches.get_many::<u64>("id").unwrap() {
+                            if let Err(e) = queue.cancel(*id) {
+                                cmd.error(clap::error::ErrorKind::InvalidValue, e).exit();
+                            }
+                        }
+                        if let Err(e) = queue.save() {
+                            cmd.error(clap::error::ErrorKind::Io, format!("save {} failed: {}", queue.get_path(), e)).exit();
+                        }
+                    },
+                    _ => {}
+                }
+                exit(0x00);
+            },
+            // the daemon sends the options stored in the jobs, the server takes receivers and msgs from requests
+            Some(("daemon", _)) | Some(("serve", _)) => {
+                match CMD::from_arg_matches(&matches) {
+                    Ok(mut c) => {
+                        c.config = config_path;
+                        c.load_key(&mut cmd);
+                        c.validate_encryption(&mut cmd);
+                        c.validate_apns(&run_file, &mut cmd);
+                        c
+                    },
+                    Err(e) => e.exit(),
+                }
+            },
             _ => {
-                match CMD::from_arg_matches(&mut matches) {
-                    Ok(c) => {
+                match CMD::from_arg_matches(&matches) {
+                    Ok(mut c) => {
+                        c.config = config_path;
+                        if let Some(name) = c.template.clone() {
+                            match run_file.get_template_by_name(&name) {
+                                Some(template) => c.apply_template(&template, &matches, &from_profile, &mut cmd),
+                                None => cmd.error(clap::error::ErrorKind::InvalidValue, format!("template {} not found", name)).exit(),
+                            }
+                        }
+                        c.load_key(&mut cmd);
                         c.validate(&mut cmd);
+                        c.validate_apns(&run_file, &mut cmd);
+                        if let Err(e) = run_file.translate_to_real_devices(&c.receiver) {
+                            cmd.error(clap::error::ErrorKind::InvalidValue, e)
+                                .exit();
+                        }
+                        // the msg of `run` is made of the command result, its stdin belongs to the command
+                        if !c.is_run() {
+                            c.load_msg(&mut cmd);
+                        }
+                        if c.is_schedule_add() {
+                            c.schedule(&mut cmd);
+                            exit(0x00);
+                        }
                         c
                     },
                     Err(e) => {
-                        eprintln!("error occured: {}", e.to_string());
+                        eprintln!("error occured: {}", e);
                         e.exit()
                     },
                 }
@@ -202,7 +825,7 @@ impl CMD {
 
     }
 
-    fn validate(&self, cmd: &mut Command) {
+    fn validate_encryption(&self, cmd: &mut Command) {
         let type_set: u8 = self.aes128 as u8 + self.aes192 as u8 + self.aes256 as u8;
         let mode_set: u8 = self.cbc as u8 + self.ecb as u8 + self.gcm as u8;
 
@@ -222,20 +845,23 @@ impl CMD {
                     cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "ase encryption type and mode are required when key is set")
                         .exit();
                 }
+                if self.iv.is_some() {
+                    cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "aes encryption type and mode are required when iv is set")
+                        .exit();
+                }
             },
             1 => {
-                let err_msg: &str;
-                if type_set == 1 {
-                    err_msg = "mode is required when aes encryption type is set";
-                 } else {
-                    err_msg = "asencryption type is required when mode is set";
-                 }
+                let err_msg: &str = if type_set == 1 {
+                    "mode is required when aes encryption type is set"
+                } else {
+                    "asencryption type is required when mode is set"
+                };
                 cmd.error(clap::error::ErrorKind::MissingRequiredArgument, err_msg)
                     .exit();
             },
             2 => {
                 if self.key.is_none() {
-                    cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "key is required when aes encryption type and mode are set")
+                    cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "key is required when aes encryption type and mode are set, pass it by --key, --key-file or --key-env")
                         .exit();
                 }
             },
@@ -245,32 +871,313 @@ impl CMD {
             },
         }
 
+        if let Some(encryption) = self.encryption() {
+            if let Err(e) = encryption.validate() {
+                cmd.error(clap::error::ErrorKind::InvalidValue, e)
+                    .exit();
+            }
+        }
+    }
+
+    /// the `[apns]` table of the run file with the `--apns-*` options must make a whole credential or none
+    fn validate_apns(&self, run_file: &RunFile, cmd: &mut Command) {
+        if let Err(e) = run_file.get_apns().merge(&self.apns).credential() {
+            cmd.error(clap::error::ErrorKind::InvalidValue, e)
+                .exit();
+        }
+    }
+
+    fn validate(&self, cmd: &mut Command) {
+        self.validate_encryption(cmd);
+
+        if !self.is_run() && self.msg.is_none() && self.msg_file.is_none() {
+            cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "msg is required, pass it by -m, --msg-file or -m - to read stdin")
+                .exit();
+        }
+
+        if let Some(CMDCommand::Schedule { command: ScheduleCommand::Add { repeat: None, .. } }) = &self.command {
+            if self.delay.is_none() && self.at.is_none() {
+                cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "--at or --delay is required to schedule a msg which does not repeat")
+                    .exit();
+            }
+        }
+
+        // the queue keeps where the key is read from, never the key itself
+        if self.is_schedule_add() && self.key.is_some() && self.key_file.is_none() && self.key_env.is_none() {
+            cmd.error(clap::error::ErrorKind::ArgumentConflict, "a scheduled msg keeps no key, pass it by --key-file or --key-env instead of -k")
+                .exit();
+        }
+
         if self.receiver.is_empty() {
             cmd.error(clap::error::ErrorKind::MissingRequiredArgument, "receiver is required and can not be empty")
                 .exit();
         }
 
+        if !(0.0..=retry_policy::MAX_BACKOFF.as_secs_f64()).contains(&self.retry_backoff) {
+            cmd.error(clap::error::ErrorKind::InvalidValue, format!("retry-backoff must be a non-negative number of seconds up to {}", retry_policy::MAX_BACKOFF.as_secs()))
+                .exit();
+        }
+
+        if !(0.0..=1.0).contains(&self.retry_jitter) {
+            cmd.error(clap::error::ErrorKind::InvalidValue, "retry-jitter must be in [0..1]")
+                .exit();
+        }
+
+        if let Some(CMDCommand::Run { failure_level: Some(level), .. }) = &self.command {
+            if msg::Level::from_str(level).is_none() {
+                cmd.error(clap::error::ErrorKind::InvalidValue, "failure-level must be one of active, timeSensitive, passive, critical")
+                    .exit();
+            }
+        }
+
         if let Some(id) = &self.id {
-            if id.as_bytes().len() >= 64 {
+            // the payload carries the id escaped
+            if msg_utils::escape_json(id).len() >= 64 {
                cmd.error(clap::error::ErrorKind::InvalidValue, "Invalid msg_id must not exceed 64 bytes.")
                    .exit();
             }
         }
     }
 
+    pub fn is_run(&self) -> bool {
+        matches!(self.command, Some(CMDCommand::Run { .. }))
+    }
+
+    pub fn is_schedule_add(&self) -> bool {
+        matches!(self.command, Some(CMDCommand::Schedule { command: ScheduleCommand::Add { .. } }))
+    }
+
+    pub fn is_daemon(&self) -> bool {
+        matches!(self.command, Some(CMDCommand::Daemon))
+    }
+
+    /// how long to wait before sending, --at is measured from now so time spent before sending counts
+    pub fn wait_time(&self) -> Option<Duration> {
+        match (self.delay, self.at) {
+            (Some(delay), _) => Some(Duration::from_secs(delay)),
+            (None, Some(at)) => Some((at - Local::now()).to_std().unwrap_or(Duration::ZERO)),
+            (None, None) => None,
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::new(
+            self.retries,
+            Duration::from_secs_f64(self.retry_backoff),
+            self.retry_jitter,
+            self.retry_max_time.map(Duration::from_secs),
+        )
+    }
+
+    /// queue the msg for `bark daemon`, the due time is fixed when it is queued
+    fn schedule(&self, cmd: &mut Command) {
+        let Some(CMDCommand::Schedule { command: ScheduleCommand::Add { repeat, catch_up } }) = &self.command else {
+            return;
+        };
+        let now: DateTime<Local> = Local::now();
+        let start: Option<DateTime<Local>> = match (self.at, self.delay) {
+            (Some(at), _) => Some(at),
+            (None, Some(delay)) => Some(now + TimeDelta::seconds(delay as i64)),
+            (None, None) => None,
+        };
+        let due: DateTime<Local> = match (repeat, start) {
+            (None, Some(start)) => start,
+            (Some(repeat), start) => match repeat.first(&start.unwrap_or(now)) {
+                Some(first) if start.is_some() || first > now => first,
+                // an interval job without a start runs after one interval
+                Some(first) => repeat.next(&first, &now).unwrap_or(first),
+                None => cmd.error(clap::error::ErrorKind::InvalidValue, format!("repeat \"{}\" never runs", repeat)).exit(),
+            },
+            (None, None) => return,
+        };
+
+        let (_lock, mut queue): (File, JobQueue) = match file_utils::lock_queue(&file_utils::queue_path(&self.config)) {
+            Ok(locked) => locked,
+            Err(e) => cmd.error(clap::error::ErrorKind::Io, e).exit(),
+        };
+        let mut options: CMD = self.clone();
+        // the daemon reads the key file again, maybe from another directory
+        if let Some(Ok(key_file)) = self.key_file.as_ref().map(std::fs::canonicalize) {
+            options.key_file = Some(key_file.to_string_lossy().to_string());
+        }
+        let id: u64 = queue.add(due, repeat.clone(), *catch_up, options, now);
+        if let Err(e) = queue.save() {
+            cmd.error(clap::error::ErrorKind::Io, format!("save {} failed: {}", queue.get_path(), e)).exit();
+        }
+        match repeat {
+            Some(repeat) => println!("job {} scheduled at {}, repeats {}", id, due.format("%Y-%m-%d %H:%M:%S %:z"), repeat),
+            None => println!("job {} scheduled at {}", id, due.format("%Y-%m-%d %H:%M:%S %:z")),
+        }
+    }
+
+    /// fill the msg options not passed on the command line from the template, with its placeholders rendered
+    ///
+    /// the template wins over the profile, `from_profile` are the ids of the options taken from it
+    fn apply_template(&mut self, template: &Template, matches: &ArgMatches, from_profile: &[String], cmd: &mut Command) {
+        let passed = |id: &str| matches!(matches.value_source(id), Some(ValueSource::CommandLine | ValueSource::EnvVariable))
+            && !from_profile.iter().any(|profile_id| profile_id == id);
+        let render = |value: Option<&str>, cmd: &mut Command| -> Option<String> {
+            let value: &str = value?;
+            match template_utils::render(value, |name| {
+                self.var.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.clone())
+                    .or_else(|| template_utils::builtin_var(name))
+            }) {
+                Ok(rendered) => Some(rendered),
+                Err(e) => cmd.error(clap::error::ErrorKind::InvalidValue, format!("template {}: {}", template.get_name(), e)).exit(),
+            }
+        };
+
+        let title: Option<String> = if passed("title") { None } else { render(template.get_title(), cmd) };
+        // a body from stdin or --msg-file is passed as well
+        let body: Option<String> = if passed("msg") || passed("msg_file") { None } else { render(template.get_body(), cmd) };
+        let level: Option<String> = if passed("level") { None } else { render(template.get_level(), cmd) };
+        let sound: Option<String> = if passed("sound") { None } else { render(template.get_sound(), cmd) };
+        let group: Option<String> = if passed("group") { None } else { render(template.get_group(), cmd) };
+        let icon: Option<String> = if passed("icon") { None } else { render(template.get_icon(), cmd) };
+        let url: Option<String> = if passed("url") { None } else { render(template.get_url(), cmd) };
+
+        if let Some(level) = &level {
+            if msg::Level::from_str(level).is_none() {
+                cmd.error(clap::error::ErrorKind::InvalidValue, format!("template {}: invalid level: {}", template.get_name(), level))
+                    .exit();
+            }
+        }
+        self.title = title.unwrap_or(self.title.clone());
+        self.msg = body.or(self.msg.take());
+        self.level = level.unwrap_or(self.level.clone());
+        self.sound = sound.unwrap_or(self.sound.clone());
+        self.group = group.or(self.group.take());
+        self.icon = icon.unwrap_or(self.icon.clone());
+        self.url = url.or(self.url.take());
+    }
+
+    /// read the key from `--key-file`, `--key-env` or the prompt of `--key -` into `self.key`
+    ///
+    /// a scheduled msg keeps only the file or the variable, `load_scheduled_key` reads the key again when it is sent
+    fn load_key(&mut self, cmd: &mut Command) {
+        match crypto_utils::read_key(self.key.as_deref(), self.key_file.as_deref(), self.key_env.as_deref()) {
+            Ok(key) => self.key = key,
+            Err(e) => cmd.error(clap::error::ErrorKind::InvalidValue, e).exit(),
+        }
+    }
+
+    /// read the key of a scheduled msg from the file or the variable it was scheduled with
+    ///
+    /// return an error if it can not be read, or if the msg is encrypted and the queue tells no place to read the key from
+    pub fn load_scheduled_key(&mut self) -> Result<(), String> {
+        self.key = crypto_utils::read_key(None, self.key_file.as_deref(), self.key_env.as_deref())?;
+        if self.key.is_none() && encryption_type(self.aes128, self.aes192, self.aes256).is_some() {
+            return Err("the key of the encrypted msg is not kept in the queue, schedule it again with --key-file or --key-env".to_string());
+        }
+        Ok(())
+    }
+
+    /// read msg content from the chosen source into `self.msg`
+    fn load_msg(&mut self, cmd: &mut Command) {
+        let (source, content) = match (self.msg.as_deref(), self.msg_file.as_deref()) {
+            (Some("-"), None) => ("stdin", msg_utils::read_msg_from_stdin()),
+            (Some(msg), None) => ("-m", Ok(msg.to_string())),
+            (_, Some(path)) => (path, msg_utils::read_msg_from_file(path)),
+            (None, None) => return,
+        };
+
+        match content {
+            Ok(content) => {
+                if source != "-m" && content.trim().is_empty() {
+                    cmd.error(clap::error::ErrorKind::InvalidValue, format!("msg read from {} is empty", source))
+                        .exit();
+                }
+                self.msg = Some(content.trim_end().to_string());
+            },
+            Err(e) => {
+                cmd.error(clap::error::ErrorKind::Io, format!("read msg from {} failed: {}", source, e))
+                    .exit();
+            }
+        }
+    }
+
+    /// the encryption passed on the command line, None if the msg is not encrypted
+    pub fn encryption(&self) -> Option<Encryption> {
+        let enc_type: EncryptionType = encryption_type(self.aes128, self.aes192, self.aes256)?;
+        let mode: EncryptionMode = encryption_mode(self.cbc, self.ecb, self.gcm)?;
+        let iv: IvPolicy = self.iv.clone().map(IvPolicy::Fixed).unwrap_or_default();
+        Some(Encryption::new(enc_type, mode, self.key.as_deref()?, self.key_format, iv))
+    }
+
+    /// the msg in clear, it tells the headers of the request
     pub fn to_msg(&self) -> Msg {
+        let mut msg: Msg = self.build_msg(self.msg.as_deref().unwrap_or_default());
+        // the header carries the id as is, only the payload needs it escaped
+        if let Some(ref id) = self.id {
+            msg.set_id(id);
+        }
+        msg
+    }
+
+    /// payload of the msg encrypted with `encryption`, the body is truncated when the payload exceeds the APNs limit
+    ///
+    /// return an error if the key can not be decoded, or the payload exceeds the APNs limit even with an empty body
+    pub fn to_payload(&self, encryption: Option<&Encryption>) -> Result<String, String> {
+        let body: String = self.msg.clone().unwrap_or_default();
+        let payload: String = self.build_payload(&body, encryption)?;
+        if payload.len() <= msg_utils::MAX_PAYLOAD_SIZE {
+            return Ok(payload);
+        }
+
+        // escaping and encryption make the payload size nonlinear to the body length,
+        // so search for the longest truncated body that still fits
+        let (mut low, mut high) = (0, body.len());
+        while low < high {
+            let mid: usize = (low + high).div_ceil(2);
+            if self.build_payload(&msg_utils::truncate(&body, mid), encryption)?.len() <= msg_utils::MAX_PAYLOAD_SIZE {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        let payload: String = self.build_payload(&msg_utils::truncate(&body, low), encryption)?;
+        if payload.len() > msg_utils::MAX_PAYLOAD_SIZE {
+            return Err(format!("payload is {} bytes even with the body cut, over the {} bytes APNs limit, shorten {}", payload.len(), msg_utils::MAX_PAYLOAD_SIZE, self.longest_fields()));
+        }
+        Ok(payload)
+    }
+
+    /// fields besides the body that take room in the payload, longest first
+    fn longest_fields(&self) -> String {
+        let mut fields: Vec<(&str, usize)> = vec![
+            ("-t", self.title.len()),
+            ("--url", self.url.as_ref().map_or(0, String::len)),
+            ("--copy", self.copy.as_ref().map_or(0, String::len)),
+            ("--group", self.group.as_ref().map_or(0, String::len)),
+            ("--sound", self.sound.len()),
+            ("--icon", self.icon.len()),
+            ("--id", self.id.as_ref().map_or(0, String::len)),
+        ];
+        fields.retain(|(_, len)| *len > 0);
+        fields.sort_by_key(|(_, len)| std::cmp::Reverse(*len));
+        fields.iter().map(|(name, len)| format!("{} ({} bytes)", name, len)).collect::<Vec<String>>().join(", ")
+    }
 
-        let mut msg: Msg = Msg::new(&self.title, &self.msg);
+    fn build_payload(&self, body: &str, encryption: Option<&Encryption>) -> Result<String, String> {
+        match encryption {
+            // a delete msg carries no body to encrypt
+            Some(encryption) if !self.delete => encryption.encrypt(&self.build_msg("NoContent").serialize(), body),
+            _ => Ok(self.build_msg(body).serialize()),
+        }
+    }
+
+    fn build_msg(&self, body: &str) -> Msg {
+        let mut msg: Msg = Msg::new(&msg_utils::escape_json(&self.title), &msg_utils::escape_json(body));
         if let Some(level) = msg::Level::from_str(&self.level) {
             msg.set_level(level);
         }
         if let Some(badge) = self.badge {
             msg.set_badge(badge);
         }
-        msg.set_sound(&self.sound);
-        msg.set_icon(&self.icon);
+        msg.set_sound(&msg_utils::escape_json(&self.sound));
+        msg.set_icon(&msg_utils::escape_json(&self.icon));
         if let Some(group) = self.group.clone() {
-            msg.set_group(&group);
+            msg.set_group(&msg_utils::escape_json(&group));
         }
         if let Some(archive) = self.archive {
             msg.set_is_archive(archive);
@@ -279,37 +1186,11 @@ impl CMD {
             msg.set_auto_copy(auto_copy);
         }
         if let Some(copy) = self.copy.clone() {
-            msg.set_copy(&copy);
+            msg.set_copy(&msg_utils::escape_json(&copy));
         }
 
         if let Some(url) = self.url.clone() {
-            msg.set_url(&url);
-        }
-
-        if let Some(key) = self.key.clone() {
-            msg.set_key(&key);
-
-            if let Some(iv) = self.iv.clone() {
-                msg.set_iv(&iv);
-            } else {
-                msg.gen_iv();
-            }
-        }
-
-        if self.aes128 {
-            msg.set_enc_type(bark_dev::msg::EncryptType::AES128);
-        } else if self.aes192 {
-            msg.set_enc_type(bark_dev::msg::EncryptType::AES192);
-        } else if self.aes256 {
-            msg.set_enc_type(bark_dev::msg::EncryptType::AES256);
-        }
-
-        if self.cbc {
-            msg.set_mode(bark_dev::msg::EncryptMode::CBC);
-        } else if self.ecb {
-            msg.set_mode(bark_dev::msg::EncryptMode::ECB);
-        } else if self.gcm {
-            msg.set_mode(bark_dev::msg::EncryptMode::GCM);
+            msg.set_url(&msg_utils::escape_json(&url));
         }
 
         if self.delete {
@@ -317,9 +1198,74 @@ impl CMD {
         }
 
         if let Some(ref id) = self.id {
-            msg.set_id(id);
+            msg.set_id(&msg_utils::escape_json(id));
         }
 
         msg
     }
-}
\ No newline at end of file
+}
+
+fn parse_at(input: &str) -> Result<DateTime<Local>, String> {
+    let now: DateTime<Local> = Local::now();
+    let at: DateTime<Local> = time_utils::parse_time(input, &now)?;
+    if at <= now {
+        return Err(format!("{} is in the past", at.format("%Y-%m-%d %H:%M:%S %:z")));
+    }
+    Ok(at)
+}
+
+fn encryption_type(aes128: bool, aes192: bool, aes256: bool) -> Option<EncryptionType> {
+    match (aes128, aes192, aes256) {
+        (true, _, _) => Some(EncryptionType::Aes128),
+        (_, true, _) => Some(EncryptionType::Aes192),
+        (_, _, true) => Some(EncryptionType::Aes256),
+        _ => None,
+    }
+}
+
+fn encryption_mode(cbc: bool, ecb: bool, gcm: bool) -> Option<EncryptionMode> {
+    match (cbc, ecb, gcm) {
+        (true, _, _) => Some(EncryptionMode::Cbc),
+        (_, true, _) => Some(EncryptionMode::Ecb),
+        (_, _, true) => Some(EncryptionMode::Gcm),
+        _ => None,
+    }
+}
+
+fn parse_var(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("{} is not like KEY=VALUE", input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_to_payload() {
+        let body: String = "a\"b".repeat(2000);
+        let cmd: CMD = CMD::try_parse_from(["bark", "-r", "alice", "-m", &body]).unwrap();
+        let payload: String = cmd.to_payload(None).unwrap();
+        assert!(payload.len() <= msg_utils::MAX_PAYLOAD_SIZE);
+        assert!(payload.contains(msg_utils::TRUNCATED_MARKER));
+
+        // the body can not be cut short enough for a long title
+        let title: String = "t".repeat(5000);
+        let cmd: CMD = CMD::try_parse_from(["bark", "-r", "alice", "-m", "hi", "-t", &title, "--group", "ops"]).unwrap();
+        let err: String = cmd.to_payload(None).unwrap_err();
+        assert!(err.contains("shorten -t (5000 bytes), --icon"), "{}", err);
+        assert!(err.ends_with("--group (3 bytes)"), "{}", err);
+    }
+
+    #[test]
+    fn test_escape_id() {
+        let cmd: CMD = CMD::try_parse_from(["bark", "-r", "alice", "-m", "hi", "--id", "a\"b\\c", "--delete"]).unwrap();
+        let payload: serde_json::Value = serde_json::from_str(&cmd.to_payload(None).unwrap()).unwrap();
+        assert!(payload.to_string().contains(r#""a\"b\\c""#), "{}", payload);
+        assert_eq!(Some("a\"b\\c".to_string()), cmd.to_msg().get_id());
+    }
+}
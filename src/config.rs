@@ -21,4 +21,14 @@
 // SOFTWARE.
 
 
-pub const RUN_FILE_BARK: &str = env!("RUN_FILE_BARK");
\ No newline at end of file
+pub const RUN_FILE_BARK: &str = env!("RUN_FILE_BARK");
+pub const TOKEN_CACHE_BARK: &str = env!("TOKEN_CACHE_BARK");
+
+/// every receiver got the msg
+pub const EXIT_ALL_DELIVERED: i32 = 0x00;
+/// no receiver got the msg
+pub const EXIT_ALL_FAILED: i32 = 0x01;
+/// bad arguments or config, same code clap exits with on usage errors
+pub const EXIT_INVALID_INPUT: i32 = 0x02;
+/// some receivers got the msg, others did not
+pub const EXIT_PARTIAL_FAILURE: i32 = 0x03;
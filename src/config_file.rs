@@ -0,0 +1,291 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{fs::File, path::Path};
+
+use toml::{Table, Value};
+
+use crate::{module::run_file::RunFile, util::file_utils};
+
+/// what is wrong with the config file at `path`: a parse error with its line and column,
+/// unknown keys, the problems of its content and a mode letting others access it, empty if nothing
+///
+/// return an error if it can not be read
+pub fn check(path: &str) -> Result<Vec<String>, String> {
+    let Some(content) = read(path)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut table: Table = match content.parse() {
+        Ok(table) => table,
+        Err(e) => return Ok(vec![e.to_string().trim_end().to_string()]),
+    };
+    let mut unknown: Vec<String> = Vec::new();
+    // an older file is checked as it reads after the upgrade, a current one from the text for the line and column of errors
+    let run_file: Result<RunFile, toml::de::Error> = if RunFile::migrate(&mut table) {
+        serde_ignored::deserialize(Value::Table(table), |key| unknown.push(key_name(&key)))
+    } else {
+        serde_ignored::deserialize(toml::Deserializer::new(&content), |key| unknown.push(key_name(&key)))
+    };
+    let run_file: RunFile = match run_file {
+        Ok(run_file) => run_file,
+        Err(e) => return Ok(vec![e.to_string().trim_end().to_string()]),
+    };
+    unknown.extend(run_file.unknown_keys());
+    let mut problems: Vec<String> = unknown.into_iter().map(|key| format!("unknown key {}", key)).collect();
+    problems.extend(run_file.problems());
+    if let Some(mode) = file_utils::shared_mode(Path::new(path)) {
+        problems.push(format!("mode {:04o} lets others than the owner access it, restrict it by `bark config fix-perms`", mode));
+    }
+    Ok(problems)
+}
+
+/// keep what parses of the config file at `path` and drop the rest: tables with a syntax error,
+/// entries of the wrong shape, unknown keys of encryption tables and later ones of a name, the file it replaces is kept as `<path>.bak`
+///
+/// unknown top level tables and unknown keys of users, groups, mappings, templates and apns are kept like on any other write
+///
+/// return what was dropped, empty if there was nothing to repair
+pub fn repair(path: &str) -> Result<Vec<String>, String> {
+    let _lock: File = file_utils::lock_file(Path::new(path)).map_err(|e| format!("lock {} failed: {}", path, e))?;
+    let Some(content) = read(path)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut dropped: Vec<String> = Vec::new();
+    let mut table: Table = Table::new();
+    for (line, chunk) in chunks(&content) {
+        match chunk.parse::<Table>() {
+            Ok(parsed) => merge(&mut table, parsed),
+            Err(e) => dropped.push(format!("lines {}-{}: {}", line, line + chunk.lines().count().saturating_sub(1), e.message().trim_end())),
+        }
+    }
+
+    RunFile::migrate(&mut table);
+    let mut kept: Table = Table::new();
+    for (key, value) in table {
+        salvage(&mut kept, key, value, &mut dropped);
+    }
+
+    let mut unknown: Vec<String> = Vec::new();
+    let mut run_file: RunFile = serde_ignored::deserialize(Value::Table(kept), |key| unknown.push(key_name(&key)))
+        .map_err(|e| format!("repair {} failed: {}", path, e))?;
+    dropped.extend(unknown.into_iter().map(|key| format!("unknown key {}", key)));
+    dropped.extend(run_file.dedupe());
+
+    // the chunks may parse one by one while the whole file does not, like a table defined twice
+    if !dropped.is_empty() || RunFile::from_string(&content).is_err() {
+        file_utils::write_runfile_to_file(path, &run_file).map_err(|e| format!("write {} failed: {}", path, e))?;
+    }
+    Ok(dropped)
+}
+
+/// dotted name of an unknown key like "group.0.color", without the `?` of the `Option`s on the way
+fn key_name(path: &serde_ignored::Path) -> String {
+    path.to_string().split('.').filter(|segment| *segment != "?").collect::<Vec<&str>>().join(".")
+}
+
+/// content of the config file, None if there is none
+fn read(path: &str) -> Result<Option<String>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("read {} failed: {}", path, e)),
+    }
+}
+
+/// `content` split before every table header, a sub table of an entry of an array of tables stays with the entry
+///
+/// return the line number every chunk starts at and its text
+fn chunks(content: &str) -> Vec<(usize, String)> {
+    let mut chunks: Vec<(usize, String)> = Vec::new();
+    // the array of tables the current chunk is an entry of
+    let mut array: Option<String> = None;
+    for (i, line) in content.lines().enumerate() {
+        if let Some((name, is_array)) = header(line) {
+            let sub_table: bool = !is_array && array.as_ref().is_some_and(|array| name.starts_with(&format!("{}.", array)));
+            if !sub_table {
+                chunks.push((i + 1, String::new()));
+                array = is_array.then_some(name);
+            }
+        }
+        if chunks.is_empty() {
+            chunks.push((i + 1, String::new()));
+        }
+        if let Some((_, chunk)) = chunks.last_mut() {
+            chunk.push_str(line);
+            chunk.push('\n');
+        }
+    }
+    chunks
+}
+
+/// name of the table a header line like `[profile.ci]` or `[[user_info]]` opens, and whether it is an array of tables
+fn header(line: &str) -> Option<(String, bool)> {
+    let line: &str = line.trim();
+    let (name, is_array) = match line.strip_prefix("[[") {
+        Some(rest) => (rest.split("]]").next()?, true),
+        None => (line.strip_prefix('[')?.split(']').next()?, false),
+    };
+    if name.trim().is_empty() || !name.chars().all(|c| c.is_alphanumeric() || "_-.\"' ".contains(c)) {
+        return None;
+    }
+    Some((name.split('.').map(str::trim).collect::<Vec<&str>>().join("."), is_array))
+}
+
+/// merge `from` into `into`, arrays of tables are appended and tables merged
+fn merge(into: &mut Table, from: Table) {
+    for (key, value) in from {
+        match (into.get_mut(&key), value) {
+            (Some(Value::Array(items)), Value::Array(more)) => items.extend(more),
+            (Some(Value::Table(table)), Value::Table(more)) => merge(table, more),
+            (_, value) => {
+                into.insert(key, value);
+            },
+        }
+    }
+}
+
+/// add `value` to `kept` if the run file still parses with it, else the entries of it which do
+fn salvage(kept: &mut Table, key: String, value: Value, dropped: &mut Vec<String>) {
+    let mut candidate: Table = kept.clone();
+    candidate.insert(key.clone(), value.clone());
+    let e: String = match parses(&candidate) {
+        Ok(()) => {
+            *kept = candidate;
+            return;
+        },
+        Err(e) => e,
+    };
+
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.into_iter().enumerate() {
+                let mut candidate: Table = kept.clone();
+                if let Value::Array(list) = candidate.entry(key.clone()).or_insert(Value::Array(Vec::new())) {
+                    list.push(item);
+                }
+                match parses(&candidate) {
+                    Ok(()) => *kept = candidate,
+                    Err(e) => dropped.push(format!("{} #{}: {}", key, i + 1, e)),
+                }
+            }
+        },
+        Value::Table(entries) => {
+            for (name, entry) in entries {
+                let mut candidate: Table = kept.clone();
+                if let Value::Table(table) = candidate.entry(key.clone()).or_insert(Value::Table(Table::new())) {
+                    table.insert(name.clone(), entry);
+                }
+                match parses(&candidate) {
+                    Ok(()) => *kept = candidate,
+                    Err(e) => dropped.push(format!("{}.{}: {}", key, name, e)),
+                }
+            }
+        },
+        _ => dropped.push(format!("{}: {}", key, e)),
+    }
+}
+
+fn parses(table: &Table) -> Result<(), String> {
+    Value::Table(table.clone()).try_into::<RunFile>()
+        .map(|_| ())
+        .map_err(|e| e.message().trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROKEN: &str = r#"[[user_info]]
+nick_name = "alice"
+device_token = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+
+[user_info.encryption]
+type = "aes128"
+mode = "ecb"
+key = "0123456789abcdef"
+padding = "pkcs7"
+
+[[user_info]]
+nick_name = "bob
+device_token = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+
+[[user_info]]
+nick_name = "carol"
+
+[[user_info]]
+nick_name = "alice"
+device_token = "cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc"
+
+[[group]]
+name = "team"
+members = ["alice", "carol"]
+color = "red"
+"#;
+
+    #[test]
+    fn test_chunks() {
+        let starts: Vec<usize> = chunks(BROKEN).into_iter().map(|(line, _)| line).collect();
+        assert_eq!(vec![1, 11, 15, 18, 22], starts);
+        assert_eq!(Some(("user_info.encryption".to_string(), false)), header(" [ user_info . encryption ] # comment"));
+        assert_eq!(Some(("user_info".to_string(), true)), header("[[user_info]]"));
+        assert_eq!(None, header("[\"a\", \"b\"]"));
+        assert_eq!(None, header("key = [1]"));
+    }
+
+    #[test]
+    fn test_check_and_repair() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("bark_repair_test").join("bark.conf");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, BROKEN).unwrap();
+        let path: &str = path.to_str().unwrap();
+        file_utils::fix_permissions(path, &format!("{}.token", path)).unwrap();
+
+        let problems: Vec<String> = check(path).unwrap();
+        assert_eq!(1, problems.len());
+        assert!(problems[0].starts_with("TOML parse error at line 12, column 17"), "{}", problems[0]);
+
+        assert_eq!(
+            vec![
+                "lines 11-14: invalid basic string".to_string(),
+                "user_info #2: missing field `device_token`".to_string(),
+                "unknown key user_info.0.encryption.padding".to_string(),
+                "user alice, the first one with the name is kept".to_string(),
+            ],
+            repair(path).unwrap()
+        );
+        assert_eq!(BROKEN, std::fs::read_to_string(format!("{}.bak", path)).unwrap());
+        // the group keeps what this bark does not know
+        assert_eq!(
+            vec![
+                "unknown key group.0.color".to_string(),
+                "member carol of group team is neither a user, a group nor a device token".to_string(),
+            ],
+            check(path).unwrap()
+        );
+        assert!(repair(path).unwrap().is_empty());
+
+        std::fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde_json::{json, Map, Value};
+
+use crate::{cmd::{CryptoCommand, EncryptionArgs}, module::encryption::{EncryptionMode, EncryptionType}, util::{crypto_utils, msg_utils}};
+
+/// run a `bark crypto` command
+///
+/// return what to print, or an error of the input
+pub fn run(command: &CryptoCommand) -> Result<String, String> {
+    match command {
+        CryptoCommand::Genkey { aes128, aes192, aes256 } => {
+            let enc_type: EncryptionType = if *aes128 {
+                EncryptionType::Aes128
+            } else if *aes192 {
+                EncryptionType::Aes192
+            } else if *aes256 {
+                EncryptionType::Aes256
+            } else {
+                return Err("aes128, aes192 or aes256 is required".to_string());
+            };
+            crypto_utils::random_text(crypto_utils::key_len(enc_type))
+        },
+        CryptoCommand::Encrypt { encryption, msg } => {
+            let (enc_type, mode, key) = required(encryption)?;
+            let iv: Option<String> = match (&encryption.iv, crypto_utils::iv_len(mode)) {
+                (Some(iv), _) => Some(iv.clone()),
+                (None, Some(len)) => Some(crypto_utils::random_text(len)?),
+                (None, None) => None,
+            };
+            let plaintext: String = input(msg)?;
+            let ciphertext: Vec<u8> = crypto_utils::encrypt(enc_type, mode, &key, iv.as_deref().map(str::as_bytes), plaintext.as_bytes())?;
+
+            let mut output: Map<String, Value> = Map::new();
+            if let Some(iv) = iv {
+                output.insert("iv".to_string(), json!(iv));
+            }
+            output.insert("ciphertext".to_string(), json!(openssl::base64::encode_block(&ciphertext)));
+            Ok(Value::Object(output).to_string())
+        },
+        CryptoCommand::Decrypt { encryption, msg } => {
+            let (enc_type, mode, key) = required(encryption)?;
+            let input: String = input(msg)?;
+            let (ciphertext, iv): (String, Option<String>) = match serde_json::from_str::<Value>(&input) {
+                Ok(Value::Object(payload)) => {
+                    let Some(ciphertext) = payload.get("ciphertext").and_then(Value::as_str) else {
+                        return Err("no ciphertext in the json".to_string());
+                    };
+                    (ciphertext.to_string(), payload.get("iv").and_then(Value::as_str).map(str::to_string))
+                },
+                _ => (input.clone(), None),
+            };
+            let iv: Option<String> = encryption.iv.clone().or(iv);
+            // the base64 of a form may come with spaces in place of '+'
+            let ciphertext: Vec<u8> = openssl::base64::decode_block(&ciphertext.trim().replace(' ', "+"))
+                .map_err(|_| "ciphertext is not base64".to_string())?;
+
+            let (plaintext, tagged) = crypto_utils::decrypt(enc_type, mode, &key, iv.as_deref().map(str::as_bytes), &ciphertext)?;
+            if mode == EncryptionMode::Gcm && !tagged {
+                eprintln!("no gcm tag matched, decrypted without authenticating it");
+            }
+            Ok(String::from_utf8_lossy(&plaintext).to_string())
+        },
+    }
+}
+
+/// type, mode and the key decoded by `--key-format`
+fn required(encryption: &EncryptionArgs) -> Result<(EncryptionType, EncryptionMode, Vec<u8>), String> {
+    match (encryption.enc_type(), encryption.mode(), encryption.key()?) {
+        (Some(enc_type), Some(mode), Some(key)) => Ok((enc_type, mode, crypto_utils::decode_key(&key, encryption.key_format)?)),
+        _ => Err("aes encryption type, mode and key are all required".to_string()),
+    }
+}
+
+/// `-m`, or stdin without its trailing newline
+fn input(msg: &Option<String>) -> Result<String, String> {
+    match msg {
+        Some(msg) => Ok(msg.clone()),
+        None => msg_utils::read_msg_from_stdin()
+            .map(|input| input.trim_end_matches(['\r', '\n']).to_string())
+            .map_err(|e| format!("read stdin failed: {}", e)),
+    }
+}
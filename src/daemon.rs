@@ -0,0 +1,121 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{collections::HashSet, fs::File, time::Duration};
+
+use chrono::{DateTime, Local};
+
+use crate::{cmd::CMD, module::{job_queue::{JobQueue, JobStatus}, send_result::SendResult}, sender::Sender, util::{file_utils, log_utils::log}};
+
+/// how often the queue is read again to pick up jobs added or cancelled meanwhile
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// send due jobs of the queue next to the config file, forever
+pub fn run(cmd: &CMD) -> ! {
+    let queue_path: String = file_utils::queue_path(&cmd.config);
+    log(&format!("watching {}", queue_path));
+
+    // runs already sent by this process, so a queue that can not be written is not sent again and again
+    let mut sent: HashSet<(u64, DateTime<Local>)> = HashSet::new();
+    loop {
+        let queue: JobQueue = match file_utils::read_queue_from_file(&queue_path) {
+            Ok(queue) => queue,
+            Err(e) => {
+                log(&e);
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        let now: DateTime<Local> = Local::now();
+        let due: Vec<(u64, DateTime<Local>)> = queue.due(now).into_iter().filter(|run| !sent.contains(run)).collect();
+        if due.is_empty() {
+            let wait: Duration = queue.next_due()
+                .and_then(|next| (next - now).to_std().ok())
+                .map_or(POLL_INTERVAL, |wait| wait.min(POLL_INTERVAL));
+            std::thread::sleep(wait);
+            continue;
+        }
+
+        for (id, due) in due {
+            sent.insert((id, due));
+            deliver(&cmd.config, &queue_path, id, due);
+        }
+    }
+}
+
+fn deliver(config_path: &str, queue_path: &str, id: u64, due: DateTime<Local>) {
+    // read again right before sending, the job may have been cancelled meanwhile
+    let mut options: CMD = match file_utils::lock_queue(queue_path) {
+        Ok((_lock, mut queue)) => match queue.get_job(id) {
+            Some(job) if job.get_status() != JobStatus::Pending || job.get_due() != due => return,
+            Some(job) if job.is_missed(Local::now()) => {
+                queue.skip(id, Local::now());
+                match (queue.save(), queue.get_job(id)) {
+                    (Err(e), _) => log(&format!("job {} missed its run at {}, but skipping it is not saved: {}", id, due.format(TIME_FORMAT), e)),
+                    (Ok(()), Some(job)) => log(&format!("job {} missed its run at {}, next run at {}", id, due.format(TIME_FORMAT), job.get_due().format(TIME_FORMAT))),
+                    (Ok(()), None) => {},
+                }
+                return;
+            },
+            Some(job) => job.get_options().clone(),
+            None => return,
+        },
+        Err(e) => {
+            log(&e);
+            return;
+        }
+    };
+    options.config = config_path.to_string();
+
+    // the queue is not locked while sending, a send with retries can take long
+    let mut sender: Sender = Sender::new(options.config.clone());
+    sender.set_retry_policy(options.retry_policy());
+    let outcome: Result<Vec<SendResult>, String> = options.load_scheduled_key().and_then(|_| sender.send(&options));
+    if let Ok(results) = &outcome {
+        SendResult::print_text(results);
+    }
+
+    let (_lock, mut queue): (File, JobQueue) = match file_utils::lock_queue(queue_path) {
+        Ok(locked) => locked,
+        Err(e) => {
+            log(&format!("job {} sent, but its status is not saved: {}", id, e));
+            return;
+        }
+    };
+    queue.finish(id, outcome, Local::now());
+    if let Err(e) = queue.save() {
+        log(&format!("job {} sent, but its status is not saved: {}", id, e));
+        return;
+    }
+    if let Some(job) = queue.get_job(id) {
+        let status: String = match job.get_last_status() {
+            Some(last_status) => format!("{}, next run at {}", last_status, job.get_due().format(TIME_FORMAT)),
+            None => job.get_status().to_string(),
+        };
+        match job.get_error() {
+            Some(e) => log(&format!("job {} {}: {}", id, status, e)),
+            None => log(&format!("job {} {}", id, status)),
+        }
+    }
+}
@@ -21,46 +21,107 @@
 // SOFTWARE.
 
 
-use std::{env, io::Write};
-use cmd::CMD;
+use std::{io::{IsTerminal, Write}, process::exit, time::{Duration, Instant}};
+use cmd::{CMDCommand, OutputFormat, CMD};
+use module::{dry_run::DryRun, send_result::SendResult};
 use sender::Sender;
 
+mod apns;
 mod config;
 mod module;
 mod cmd;
 mod util;
+mod runner;
 mod sender;
+mod daemon;
+mod server;
+mod crypto;
+mod config_file;
 
 fn main() {
     let cmd: CMD = CMD::parse();
 
-    let msg: bark_dev::msg::Msg = cmd.to_msg();
+    if cmd.is_daemon() {
+        daemon::run(&cmd);
+    }
+
+    if let Some(CMDCommand::Serve(args)) = &cmd.command {
+        exit(server::run(&cmd, args));
+    }
+
+    if cmd.is_run() {
+        let (notify, code) = runner::run(&cmd);
+        if let Some(notify) = notify {
+            deliver(&notify);
+        }
+        exit(code);
+    }
+
+    exit(deliver(&cmd));
+}
+
+/// send the msg described by `cmd`
+///
+/// return the exit code summarizing the send result
+fn deliver(cmd: &CMD) -> i32 {
+    if cmd.dry_run {
+        let dry_run: DryRun = match Sender::new(cmd.config.clone()).dry_run(cmd) {
+            Ok(dry_run) => dry_run,
+            Err(e) => {
+                eprintln!("{}", e);
+                return config::EXIT_INVALID_INPUT;
+            }
+        };
+        match cmd.output {
+            OutputFormat::Text => dry_run.print_text(),
+            OutputFormat::Json => dry_run.print_json(),
+        }
+        return if dry_run.has_valid_device() { config::EXIT_ALL_DELIVERED } else { config::EXIT_INVALID_INPUT };
+    }
     
-    if let Some(delay) = cmd.delay {
-        count_down(delay);
+    if let Some(wait) = cmd.wait_time() {
+        if cmd.no_countdown || !std::io::stderr().is_terminal() {
+            std::thread::sleep(wait);
+        } else {
+            count_down(wait);
+        }
     }
 
-    let bark_file = env::var("RUN_FILE_BARK")
-        .unwrap_or_else(|_| {
-            config::RUN_FILE_BARK.to_string() 
-    });
+    let mut send: Sender = Sender::new(cmd.config.clone());
+    send.set_retry_policy(cmd.retry_policy());
 
-    let mut send: Sender = Sender::new(bark_file);
+    let results: Vec<SendResult> = match send.send(cmd) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{}", e);
+            return config::EXIT_INVALID_INPUT;
+        }
+    };
 
-    send.send(&msg, &cmd.receiver);
+    match cmd.output {
+        OutputFormat::Text => SendResult::print_text(&results),
+        OutputFormat::Json => SendResult::print_json(&results),
+    }
+    SendResult::exit_code(&results)
 }
 
-// show count down in terminal
-fn count_down(delay: u64) {
-    let mut stdout: std::io::Stdout = std::io::stdout();
-    for i in (0..=delay).rev() {
-        print!("\rAfter {} seconds, the message will be sent", i);
-        stdout.flush().unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        if i != 0 {
-            stdout.write_all(b"\r").unwrap();
-            stdout.write_all(b"\x1b[K").unwrap();
-            stdout.flush().unwrap();
+// show count down in terminal, on stderr so stdout only carries the send result
+// sleeps towards a deadline so long waits don't drift
+fn count_down(wait: Duration) {
+    let mut stderr: std::io::Stderr = std::io::stderr();
+    let deadline: Instant = Instant::now() + wait;
+    loop {
+        let remaining: Duration = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
         }
+        let secs: u64 = remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64;
+        eprint!("\r\x1b[KAfter {} seconds, the message will be sent", secs);
+        stderr.flush().unwrap();
+        // wake up on whole seconds before the deadline
+        let tick: Duration = remaining - Duration::from_secs(secs - 1);
+        std::thread::sleep(tick);
     }
+    stderr.write_all(b"\r\x1b[K").unwrap();
+    stderr.flush().unwrap();
 }
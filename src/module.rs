@@ -23,4 +23,19 @@
 
 mod token;
 pub mod user_info;
+pub mod group_info;
+pub mod mapping;
 pub mod run_file;
+pub mod send_result;
+pub mod retry_policy;
+pub mod dry_run;
+pub mod job_queue;
+pub mod repeat;
+pub mod push_request;
+pub mod alertmanager;
+pub mod webhook;
+pub mod template;
+pub mod profile;
+pub mod encryption;
+pub mod apns_config;
+pub mod token_cache;
@@ -0,0 +1,232 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, Utc};
+use serde::Deserialize;
+
+use crate::cmd::CMD;
+
+/// labels shown in the title or mapped to options, not repeated in the body
+const HIDDEN_LABELS: [&str; 2] = ["alertname", "severity"];
+
+/// what a resolved alert does to the notification of the firing one
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlertResolved {
+    /// show the resolved notification in its place
+    Replace,
+    /// remove it silently
+    Delete,
+}
+
+/// body of an Alertmanager webhook
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    #[serde(default)]
+    receiver: String,
+    #[serde(default)]
+    alerts: Vec<Alert>,
+    #[serde(default, rename = "externalURL")]
+    external_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+    starts_at: Option<DateTime<Utc>>,
+    ends_at: Option<DateTime<Utc>>,
+    #[serde(default, rename = "generatorURL")]
+    generator_url: String,
+    #[serde(default)]
+    fingerprint: String,
+}
+
+impl Notification {
+    pub fn from_json(body: &[u8]) -> Result<Notification, String> {
+        serde_json::from_slice(body).map_err(|e| format!("invalid alertmanager webhook: {}", e))
+    }
+
+    pub fn get_alerts(&self) -> &[Alert] {
+        &self.alerts
+    }
+
+    /// values of `labels` of an alert which are `known` receivers, in order
+    ///
+    /// the `receiver` label falls back to the receiver of the webhook
+    pub fn receivers(&self, alert: &Alert, labels: &[String], known: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for label in labels {
+            let value: Option<&String> = match alert.labels.get(label) {
+                None if label == "receiver" => Some(&self.receiver),
+                value => value,
+            };
+            if let Some(value) = value {
+                if known(value) && !names.contains(value) {
+                    names.push(value.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// the options to send `alert` with, options not set by the alert keep the ones of `base`
+    ///
+    /// the fingerprint is the msg id, so the resolved msg replaces or deletes the firing one
+    pub fn to_cmd(&self, alert: &Alert, base: &CMD, receivers: Vec<String>, resolved: AlertResolved) -> CMD {
+        let mut cmd: CMD = base.clone();
+        let name: &str = alert.labels.get("alertname").map_or("alert", |n| n.as_str());
+
+        cmd.receiver = receivers;
+        cmd.title = format!("[{}] {}", if alert.is_resolved() { "RESOLVED" } else { "FIRING" }, name);
+        cmd.msg = Some(alert.body());
+        cmd.level = if alert.is_resolved() {
+            "passive".to_string()
+        } else {
+            match alert.labels.get("severity").map(|s| s.as_str()) {
+                Some("critical") => "timeSensitive".to_string(),
+                Some("warning") => "active".to_string(),
+                Some("info") => "passive".to_string(),
+                _ => cmd.level,
+            }
+        };
+        cmd.group = cmd.group.or(Some(name.to_string()));
+        cmd.url = [&alert.generator_url, &self.external_url].into_iter()
+            .find(|u| !u.is_empty())
+            .cloned()
+            .or(cmd.url);
+        if !alert.fingerprint.is_empty() && alert.fingerprint.len() < 64 {
+            cmd.id = Some(alert.fingerprint.clone());
+            cmd.delete = alert.is_resolved() && resolved == AlertResolved::Delete;
+        }
+        cmd
+    }
+}
+
+impl Alert {
+    pub fn is_resolved(&self) -> bool {
+        self.status == "resolved"
+    }
+
+    /// summary, description, the other labels and since when
+    fn body(&self) -> String {
+        let mut lines: Vec<String> = ["summary", "description"].iter()
+            .filter_map(|a| self.annotations.get(*a))
+            .filter(|a| !a.is_empty())
+            .cloned()
+            .collect();
+
+        let labels: Vec<String> = self.labels.iter()
+            .filter(|(k, _)| !HIDDEN_LABELS.contains(&k.as_str()))
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        if !labels.is_empty() {
+            lines.push(labels.join(", "));
+        }
+
+        let (event, at) = if self.is_resolved() { ("resolved", self.ends_at) } else { ("started", self.starts_at) };
+        if let Some(at) = at.filter(|at| at.timestamp() > 0) {
+            lines.push(format!("{}: {}", event, at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    const WEBHOOK: &str = r#"{
+        "version": "4",
+        "groupKey": "{}:{alertname=\"HighCPU\"}",
+        "status": "firing",
+        "receiver": "oncall",
+        "externalURL": "http://alertmanager:9093",
+        "alerts": [
+            {
+                "status": "firing",
+                "labels": {"alertname": "HighCPU", "severity": "critical", "team": "dba", "instance": "db1:9100"},
+                "annotations": {"summary": "CPU above 90%"},
+                "startsAt": "2026-10-18T10:00:00.123Z",
+                "endsAt": "0001-01-01T00:00:00Z",
+                "generatorURL": "http://prometheus:9090/graph",
+                "fingerprint": "4c7c5b0f3ad3e7f1"
+            },
+            {
+                "status": "resolved",
+                "labels": {"alertname": "DiskFull", "team": "nobody"},
+                "annotations": {},
+                "startsAt": "2026-10-18T09:00:00Z",
+                "endsAt": "2026-10-18T10:00:00Z",
+                "fingerprint": "0a1b2c3d4e5f6071"
+            }
+        ]
+    }"#;
+
+    fn base() -> CMD {
+        CMD::try_parse_from(["bark", "-r", "fallback", "-m", "-"]).unwrap()
+    }
+
+    #[test]
+    fn test_receivers() {
+        let n = Notification::from_json(WEBHOOK.as_bytes()).unwrap();
+        let labels: Vec<String> = vec!["team".to_string(), "receiver".to_string()];
+        let known = |name: &str| ["dba", "oncall"].contains(&name);
+        assert_eq!(vec!["dba".to_string(), "oncall".to_string()], n.receivers(&n.get_alerts()[0], &labels, known));
+        assert_eq!(vec!["oncall".to_string()], n.receivers(&n.get_alerts()[1], &labels, known));
+        assert!(n.receivers(&n.get_alerts()[1], &labels[..1], known).is_empty());
+    }
+
+    #[test]
+    fn test_to_cmd() {
+        let n = Notification::from_json(WEBHOOK.as_bytes()).unwrap();
+
+        let firing = n.to_cmd(&n.get_alerts()[0], &base(), vec!["dba".to_string()], AlertResolved::Delete);
+        assert_eq!("[FIRING] HighCPU", firing.title);
+        assert!(firing.msg.as_ref().unwrap().starts_with("CPU above 90%\ninstance=db1:9100, team=dba\nstarted: "));
+        assert_eq!("timeSensitive", firing.level);
+        assert_eq!(Some("HighCPU".to_string()), firing.group);
+        assert_eq!(Some("http://prometheus:9090/graph".to_string()), firing.url);
+        assert_eq!((Some("4c7c5b0f3ad3e7f1".to_string()), false), (firing.id, firing.delete));
+        assert_eq!(vec!["dba".to_string()], firing.receiver);
+
+        let resolved = n.to_cmd(&n.get_alerts()[1], &base(), vec![], AlertResolved::Replace);
+        assert_eq!("[RESOLVED] DiskFull", resolved.title);
+        assert_eq!("passive", resolved.level);
+        assert_eq!(Some("http://alertmanager:9093".to_string()), resolved.url);
+        assert_eq!((Some("0a1b2c3d4e5f6071".to_string()), false), (resolved.id, resolved.delete));
+
+        let deleted = n.to_cmd(&n.get_alerts()[1], &base(), vec![], AlertResolved::Delete);
+        assert!(deleted.delete);
+
+        assert!(Notification::from_json(b"{\"alerts\": 1}").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use openssl::{ec::EcKey, pkey::{PKey, Private}};
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+/// which APNs to push to
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ApnsEnvironment {
+    /// api.push.apple.com, for the app installed from the App Store or TestFlight
+    #[default]
+    Production,
+    /// api.sandbox.push.apple.com, for a development build of the app
+    Sandbox,
+}
+
+impl ApnsEnvironment {
+    pub fn host(&self) -> &'static str {
+        match self {
+            ApnsEnvironment::Production => "api.push.apple.com",
+            ApnsEnvironment::Sandbox => "api.sandbox.push.apple.com",
+        }
+    }
+}
+
+/// the `[apns]` table of the config file, the `--apns-*` options win over it
+///
+/// without key_file, key_id, team_id and topic the credential of the Bark app built in `bark_dev` is used
+#[derive(clap::Args, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ApnsConfig {
+    /// .p8 key of your own APNs credential, used with --apns-key-id, --apns-team-id and --apns-topic
+    #[arg(long = "apns-key-file", id = "apns_key_file", value_name = "APNS_KEY_FILE", required = false)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_file: Option<String>,
+    /// Key ID of the .p8 key
+    #[arg(long = "apns-key-id", id = "apns_key_id", value_name = "APNS_KEY_ID", required = false)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_id: Option<String>,
+    /// Team ID of the developer account the key belongs to
+    #[arg(long = "apns-team-id", id = "apns_team_id", value_name = "APNS_TEAM_ID", required = false)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    team_id: Option<String>,
+    /// bundle id of your build of the Bark app
+    #[arg(long = "apns-topic", id = "apns_topic", value_name = "APNS_TOPIC", required = false)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
+    /// APNs to push to [default: production]
+    #[arg(long = "apns-env", id = "apns_env", value_name = "APNS_ENV", required = false, value_enum)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environment: Option<ApnsEnvironment>,
+    /// settings of the table this bark does not know, written back as they are
+    #[arg(skip)]
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+/// an APNs signing key and the app it pushes to
+pub struct Credential {
+    key_id: String,
+    team_id: String,
+    topic: String,
+    key: EcKey<Private>,
+}
+
+impl Credential {
+    /// the id its token is cached by, a new key gets a new Key ID
+    pub fn id(&self) -> String {
+        format!("{}.{}", self.team_id, self.key_id)
+    }
+    pub fn get_key_id(&self) -> &str {
+        &self.key_id
+    }
+    pub fn get_team_id(&self) -> &str {
+        &self.team_id
+    }
+    pub fn get_topic(&self) -> &str {
+        &self.topic
+    }
+    pub fn get_key(&self) -> &EcKey<Private> {
+        &self.key
+    }
+}
+
+impl ApnsConfig {
+    /// `self` with the fields set in `other` replaced
+    pub fn merge(&self, other: &ApnsConfig) -> ApnsConfig {
+        ApnsConfig {
+            key_file: other.key_file.clone().or(self.key_file.clone()),
+            key_id: other.key_id.clone().or(self.key_id.clone()),
+            team_id: other.team_id.clone().or(self.team_id.clone()),
+            topic: other.topic.clone().or(self.topic.clone()),
+            environment: other.environment.or(self.environment),
+            unknown: self.unknown.clone(),
+        }
+    }
+
+    pub fn environment(&self) -> ApnsEnvironment {
+        self.environment.unwrap_or_default()
+    }
+    pub fn unknown_keys(&self) -> Vec<String> {
+        self.unknown.keys().cloned().collect()
+    }
+
+    /// the own credential, None to use the built-in one
+    ///
+    /// return an error if only some of key_file, key_id, team_id and topic are set, or the key can not be read
+    pub fn credential(&self) -> Result<Option<Credential>, String> {
+        let fields: [(&str, &Option<String>); 4] = [("key_file", &self.key_file), ("key_id", &self.key_id), ("team_id", &self.team_id), ("topic", &self.topic)];
+        let missing: Vec<&str> = fields.iter().filter(|(_, value)| value.is_none()).map(|(name, _)| *name).collect();
+        if missing.len() == fields.len() {
+            return Ok(None);
+        }
+        if !missing.is_empty() {
+            return Err(format!("apns key_file, key_id, team_id and topic are all required to push with your own credential, missing: {}", missing.join(", ")));
+        }
+
+        let path: &str = self.key_file.as_deref().unwrap_or_default();
+        let pem: Vec<u8> = std::fs::read(path).map_err(|e| format!("read apns key {} failed: {}", path, e))?;
+        let key: EcKey<Private> = PKey::private_key_from_pem(&pem)
+            .and_then(|key| key.ec_key())
+            .map_err(|_| format!("apns key {} is not a .p8 key", path))?;
+        Ok(Some(Credential {
+            key_id: self.key_id.clone().unwrap_or_default(),
+            team_id: self.team_id.clone().unwrap_or_default(),
+            topic: self.topic.clone().unwrap_or_default(),
+            key,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apns_config() {
+        let file: ApnsConfig = toml::from_str("key_id = \"KEY\"\nteam_id = \"TEAM\"\nenvironment = \"sandbox\"\n").unwrap();
+        assert_eq!(ApnsEnvironment::Sandbox, file.environment());
+        assert_eq!(ApnsEnvironment::Production, ApnsConfig::default().environment());
+
+        let merged: ApnsConfig = file.merge(&ApnsConfig { key_id: Some("OTHER".to_string()), ..Default::default() });
+        assert_eq!(Some("OTHER".to_string()), merged.key_id);
+        assert_eq!(Some("TEAM".to_string()), merged.team_id);
+        assert_eq!(ApnsEnvironment::Sandbox, merged.environment());
+        assert_eq!("key_id = \"OTHER\"\nteam_id = \"TEAM\"\nenvironment = \"sandbox\"\n", toml::to_string(&merged).unwrap());
+
+        assert!(ApnsConfig::default().credential().unwrap().is_none());
+        assert_eq!(
+            Some("apns key_file, key_id, team_id and topic are all required to push with your own credential, missing: key_file, topic".to_string()),
+            merged.credential().err()
+        );
+    }
+
+    #[test]
+    fn test_credential() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("bark_apns_key_test.p8");
+        let group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let key: PKey<Private> = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        std::fs::write(&path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let mut config: ApnsConfig = ApnsConfig {
+            key_file: path.to_str().map(str::to_string),
+            key_id: Some("KEY".to_string()),
+            team_id: Some("TEAM".to_string()),
+            topic: Some("com.example.bark".to_string()),
+            ..Default::default()
+        };
+        let credential: Credential = config.credential().unwrap().unwrap();
+        assert_eq!("TEAM.KEY", credential.id());
+        assert_eq!("com.example.bark", credential.get_topic());
+
+        std::fs::write(&path, "not a key").unwrap();
+        assert!(config.credential().is_err());
+        std::fs::remove_file(&path).unwrap();
+        config.key_file = Some("/not/exist.p8".to_string());
+        assert!(config.credential().is_err());
+    }
+}
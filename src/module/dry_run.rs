@@ -0,0 +1,134 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use serde::{Serialize, Serializer};
+use serde_json::value::RawValue;
+
+use super::send_result::SendResult;
+
+/// a receiver the msg would be sent to
+#[derive(Serialize, Clone, Debug)]
+pub struct DryRunDevice {
+    alias: String,
+    device_token: String,
+    /// false if it is neither an alias nor a valid device token, it would be skipped
+    valid: bool,
+    /// the payload of a device whose user has an own encryption
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<Box<RawValue>>,
+}
+
+/// everything a send would put on the wire, without sending it
+#[derive(Serialize, Debug)]
+pub struct DryRun {
+    /// APNs host the request would go to
+    host: String,
+    #[serde(serialize_with = "serialize_headers")]
+    headers: Vec<(String, String)>,
+    /// the payload byte for byte, embedded as json if it is valid json, else as a string
+    payload: Box<RawValue>,
+    devices: Vec<DryRunDevice>,
+}
+
+fn serialize_headers<S: Serializer>(headers: &[(String, String)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(headers.iter().map(|(k, v)| (k, v)))
+}
+
+impl DryRun {
+    pub fn new(host: &str, headers: Vec<(&str, String)>, payload: &str, devices: Vec<(String, String, bool, Option<String>)>) -> DryRun {
+        DryRun {
+            host: host.to_string(),
+            headers: headers.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            payload: Self::raw_payload(payload),
+            devices: devices.into_iter().map(|(alias, device_token, valid, payload)| DryRunDevice {
+                alias,
+                device_token: SendResult::mask_device_token(&device_token),
+                valid,
+                payload: payload.as_deref().map(Self::raw_payload),
+            }).collect(),
+        }
+    }
+
+    fn raw_payload(payload: &str) -> Box<RawValue> {
+        RawValue::from_string(payload.to_string())
+            .or_else(|_| serde_json::value::to_raw_value(payload))
+            .expect("a string is always valid json")
+    }
+
+    pub fn has_valid_device(&self) -> bool {
+        self.devices.iter().any(|d| d.valid)
+    }
+
+    pub fn print_text(&self) {
+        println!("host: {}", self.host);
+        println!("headers:");
+        self.headers.iter().for_each(|(k, v)| println!("  {}: {}", k, v));
+
+        println!("payload:");
+        println!("  {}", self.payload.get());
+
+        println!("devices:");
+        let max_alias_len: usize = self.devices.iter().map(|d| d.alias.len()).max().unwrap_or(1);
+        self.devices.iter().for_each(|d| {
+            println!("  {:<max_alias_len$}    {}{}", d.alias, d.device_token, if d.valid { "" } else { " (invalid, skipped)" });
+            if let Some(payload) = &d.payload {
+                println!("  {:<max_alias_len$}    payload: {}", "", payload.get());
+            }
+        });
+    }
+
+    pub fn print_json(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("serialize dry run failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dry_run_json() {
+        let dry_run = DryRun::new(
+            "api.push.apple.com",
+            vec![("apns-push-type", "alert".to_string())],
+            "{\"aps\":{\"alert\":{\"title\":\"t\",\"body\":\"b\"}}}",
+            vec![
+                ("alice".to_string(), "0123abcdefghijklmnopqrstuvwxyz".to_string(), true, None),
+                ("bob".to_string(), "abcdefghijklmnopqrstuvwxyz0123".to_string(), true, Some("{\"ciphertext\":\"c\"}".to_string())),
+                ("x".to_string(), "x".to_string(), false, None),
+            ],
+        );
+        assert!(dry_run.has_valid_device());
+        assert_eq!(
+            "{\"host\":\"api.push.apple.com\",\"headers\":{\"apns-push-type\":\"alert\"},\"payload\":{\"aps\":{\"alert\":{\"title\":\"t\",\"body\":\"b\"}}},\"devices\":[{\"alias\":\"alice\",\"device_token\":\"0123ab…wxyz\",\"valid\":true},{\"alias\":\"bob\",\"device_token\":\"abcdef…0123\",\"valid\":true,\"payload\":{\"ciphertext\":\"c\"}},{\"alias\":\"x\",\"device_token\":\"*\",\"valid\":false}]}",
+            serde_json::to_string(&dry_run).unwrap()
+        );
+
+        let dry_run = DryRun::new("api.sandbox.push.apple.com", vec![], "not json", vec![]);
+        assert!(!dry_run.has_valid_device());
+        assert_eq!("{\"host\":\"api.sandbox.push.apple.com\",\"headers\":{},\"payload\":\"not json\",\"devices\":[]}", serde_json::to_string(&dry_run).unwrap());
+    }
+}
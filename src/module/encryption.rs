@@ -0,0 +1,246 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::{crypto_utils, msg_utils};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionType {
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionMode {
+    Cbc,
+    Ecb,
+    Gcm,
+}
+
+/// how the key is written
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyFormat {
+    /// the characters typed into the Bark app
+    #[default]
+    Text,
+    /// hex of the key bytes
+    Hex,
+    /// base64 of the key bytes
+    Base64,
+}
+
+/// where the iv of a msg comes from, "random" or the iv itself in the run file
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(from = "String", into = "String")]
+pub enum IvPolicy {
+    /// a new iv for every msg
+    #[default]
+    Random,
+    Fixed(String),
+}
+
+/// how the msg to a device is encrypted, it must be the same as set in the Bark app of the device
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Encryption {
+    #[serde(rename = "type")]
+    enc_type: EncryptionType,
+    mode: EncryptionMode,
+    key: String,
+    #[serde(default, skip_serializing_if = "KeyFormat::is_text")]
+    key_format: KeyFormat,
+    #[serde(default)]
+    iv: IvPolicy,
+}
+
+impl KeyFormat {
+    pub fn is_text(&self) -> bool {
+        *self == KeyFormat::Text
+    }
+}
+
+impl Encryption {
+    pub fn new(enc_type: EncryptionType, mode: EncryptionMode, key: &str, key_format: KeyFormat, iv: IvPolicy) -> Self {
+        Encryption {
+            enc_type,
+            mode,
+            key: key.to_string(),
+            key_format,
+            iv,
+        }
+    }
+
+    /// check the key and the iv fit the type and the mode
+    pub fn validate(&self) -> Result<(), String> {
+        crypto_utils::check_key(self.enc_type, &crypto_utils::decode_key(&self.key, self.key_format)?)?;
+        match &self.iv {
+            IvPolicy::Fixed(iv) => crypto_utils::check_iv(self.mode, Some(iv.as_bytes())),
+            // generated with the length of the mode
+            IvPolicy::Random => Ok(()),
+        }
+    }
+
+    /// encrypt `{"body":"<body>"}` into `payload`, a payload of the msg whose body is left out
+    ///
+    /// the iv and the ciphertext are appended to the fields in clear, the same as `bark_dev` does
+    pub fn encrypt(&self, payload: &str, body: &str) -> Result<String, String> {
+        let key: Vec<u8> = crypto_utils::decode_key(&self.key, self.key_format)?;
+        let iv: Option<String> = match (&self.iv, crypto_utils::iv_len(self.mode)) {
+            (IvPolicy::Fixed(iv), _) => Some(iv.clone()),
+            (IvPolicy::Random, Some(len)) => Some(crypto_utils::random_text(len)?),
+            (IvPolicy::Random, None) => None,
+        };
+        let plaintext: String = format!("{{\"body\":\"{}\"}}", msg_utils::escape_json(body));
+        let ciphertext: Vec<u8> = crypto_utils::encrypt(self.enc_type, self.mode, &key, iv.as_deref().map(str::as_bytes), plaintext.as_bytes())?;
+
+        let mut encrypted: String = payload.strip_suffix('}').unwrap_or(payload).to_string();
+        if let Some(iv) = iv {
+            encrypted += &format!(",\"iv\":\"{}\"", msg_utils::escape_json(&iv));
+        }
+        encrypted += &format!(",\"ciphertext\":\"{}\"}}", openssl::base64::encode_block(&ciphertext));
+        Ok(encrypted)
+    }
+}
+
+impl std::fmt::Display for EncryptionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionType::Aes128 => write!(f, "aes128"),
+            EncryptionType::Aes192 => write!(f, "aes192"),
+            EncryptionType::Aes256 => write!(f, "aes256"),
+        }
+    }
+}
+
+impl std::fmt::Display for EncryptionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionMode::Cbc => write!(f, "cbc"),
+            EncryptionMode::Ecb => write!(f, "ecb"),
+            EncryptionMode::Gcm => write!(f, "gcm"),
+        }
+    }
+}
+
+/// like "aes256-gcm", the key is never shown
+impl std::fmt::Display for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.enc_type, self.mode)
+    }
+}
+
+impl From<String> for IvPolicy {
+    fn from(s: String) -> Self {
+        if s == "random" {
+            IvPolicy::Random
+        } else {
+            IvPolicy::Fixed(s)
+        }
+    }
+}
+
+impl From<IvPolicy> for String {
+    fn from(iv: IvPolicy) -> String {
+        match iv {
+            IvPolicy::Random => "random".to_string(),
+            IvPolicy::Fixed(iv) => iv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bark_dev::msg::{EncryptMode, EncryptType, Msg};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct User {
+        encryption: Encryption,
+    }
+
+    #[test]
+    fn test_encryption_toml() {
+        let user: User = toml::from_str("[encryption]\ntype = \"aes192\"\nmode = \"gcm\"\nkey = \"123456789012345678901234\"\n").unwrap();
+        assert_eq!(Encryption::new(EncryptionType::Aes192, EncryptionMode::Gcm, "123456789012345678901234", KeyFormat::Text, IvPolicy::Random), user.encryption);
+        assert_eq!("aes192-gcm", user.encryption.to_string());
+        assert_eq!(
+            "[encryption]\ntype = \"aes192\"\nmode = \"gcm\"\nkey = \"123456789012345678901234\"\niv = \"random\"\n",
+            toml::to_string(&user).unwrap()
+        );
+
+        let user: User = toml::from_str("[encryption]\ntype = \"aes256\"\nmode = \"cbc\"\nkey = \"k\"\nkey_format = \"hex\"\niv = \"123456789012\"\n").unwrap();
+        assert_eq!(IvPolicy::Fixed("123456789012".to_string()), user.encryption.iv);
+        assert_eq!(KeyFormat::Hex, user.encryption.key_format);
+        assert!(toml::from_str::<User>("[encryption]\ntype = \"des\"\nmode = \"cbc\"\nkey = \"k\"\n").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let encryption = |enc_type, mode, key: &str, key_format, iv: &str| Encryption::new(enc_type, mode, key, key_format, IvPolicy::from(iv.to_string()));
+        assert!(encryption(EncryptionType::Aes128, EncryptionMode::Cbc, "1234567890123456", KeyFormat::Text, "random").validate().is_ok());
+        assert!(encryption(EncryptionType::Aes256, EncryptionMode::Gcm, &"ab".repeat(32), KeyFormat::Hex, "123456789012").validate().is_ok());
+        assert!(encryption(EncryptionType::Aes128, EncryptionMode::Ecb, "MTIzNDU2Nzg5MDEyMzQ1Ng==", KeyFormat::Base64, "random").validate().is_ok());
+        assert_eq!(
+            Err("key of aes256 must be 32 bytes, got 10".to_string()),
+            encryption(EncryptionType::Aes256, EncryptionMode::Gcm, "0123456789", KeyFormat::Text, "random").validate()
+        );
+        assert_eq!(
+            Err("iv of cbc mode must be 16 bytes, got 12".to_string()),
+            encryption(EncryptionType::Aes128, EncryptionMode::Cbc, "1234567890123456", KeyFormat::Text, "123456789012").validate()
+        );
+        assert_eq!(
+            Err("ecb mode takes no iv".to_string()),
+            encryption(EncryptionType::Aes128, EncryptionMode::Ecb, "1234567890123456", KeyFormat::Text, "1234567890123456").validate()
+        );
+    }
+
+    #[test]
+    fn test_encrypt_like_bark_dev() {
+        // `bark_dev` only takes a 24 characters key and a 12 characters iv, which fits aes192 in gcm mode
+        let mut msg: Msg = Msg::new("title", "hello \\\"world\\\"");
+        msg.set_key("123456789012345678901234");
+        msg.set_iv("abcdefghijkl");
+        msg.set_enc_type(EncryptType::AES192);
+        msg.set_mode(EncryptMode::GCM);
+
+        let encryption: Encryption = Encryption::new(EncryptionType::Aes192, EncryptionMode::Gcm, "123456789012345678901234", KeyFormat::Text, IvPolicy::Fixed("abcdefghijkl".to_string()));
+        let payload: String = Msg::new("title", "NoContent").serialize();
+        assert_eq!(msg.serialize(), encryption.encrypt(&payload, "hello \"world\"").unwrap());
+    }
+
+    #[test]
+    fn test_encrypt_random_iv() {
+        let encryption: Encryption = Encryption::new(EncryptionType::Aes128, EncryptionMode::Cbc, "1234567890123456", KeyFormat::Text, IvPolicy::Random);
+        let payload: serde_json::Value = serde_json::from_str(&encryption.encrypt("{\"aps\":{}}", "hello").unwrap()).unwrap();
+        let iv: &str = payload["iv"].as_str().unwrap();
+        assert_eq!(16, iv.len());
+
+        let ciphertext: Vec<u8> = openssl::base64::decode_block(payload["ciphertext"].as_str().unwrap()).unwrap();
+        let (plaintext, _) = crypto_utils::decrypt(EncryptionType::Aes128, EncryptionMode::Cbc, b"1234567890123456", Some(iv.as_bytes()), &ciphertext).unwrap();
+        assert_eq!(b"{\"body\":\"hello\"}".to_vec(), plaintext);
+    }
+}
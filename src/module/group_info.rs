@@ -0,0 +1,111 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use serde::{Serialize, Deserialize};
+use toml::Table;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupInfo {
+    name: String,
+    members: Vec<String>,
+    /// settings of the group this bark does not know, written back as they are
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+const HEAD_NAME: &str = "GROUP";
+const HEAD_MEMBERS: &str = "MEMBERS";
+
+impl GroupInfo {
+    pub fn new(name: &str, members: Vec<String>) -> GroupInfo {
+        GroupInfo {
+            name: name.to_string(),
+            members,
+            unknown: Table::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_members(&self) -> &Vec<String> {
+        &self.members
+    }
+    pub fn unknown_keys(&self) -> Vec<String> {
+        self.unknown.keys().cloned().collect()
+    }
+
+    pub fn pretty_print(groups: Vec<GroupInfo>) {
+        let max_name_len: usize = std::cmp::max(groups.iter().map(|g| g.name.len()).max().unwrap_or(1), HEAD_NAME.len());
+
+        println!("{:<max_name_len$}    {}", HEAD_NAME, HEAD_MEMBERS);
+        println!("{:-<max_name_len$}    {:-<2$}", "", "", HEAD_MEMBERS.len());
+
+        groups.iter().for_each(|g| {
+            println!("{:<max_name_len$}    {}", g.name, g.members.join(","));
+        });
+    }
+}
+
+impl std::str::FromStr for GroupInfo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, members) = s.split_once(":").unwrap_or(("",""));
+        let members: Vec<String> = members.split(',')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect();
+        if name.is_empty() || members.is_empty() {
+            return Err("Please input valid char like \"group:member1,member2\"".to_string());
+        }
+        Ok(GroupInfo::new(name, members))
+    }
+}
+
+impl std::fmt::Display for GroupInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {}", self.name, self.members.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_group_info_from_str() {
+        let g = "oncall:alice, bob,,group2".parse::<GroupInfo>().unwrap();
+        assert_eq!("oncall", g.get_name());
+        assert_eq!(&vec!["alice".to_string(), "bob".to_string(), "group2".to_string()], g.get_members());
+
+        assert!("oncall".parse::<GroupInfo>().is_err());
+        assert!("oncall:".parse::<GroupInfo>().is_err());
+        assert!(":alice".parse::<GroupInfo>().is_err());
+
+        GroupInfo::pretty_print(vec![g]);
+        println!();
+        GroupInfo::pretty_print(vec![]);
+    }
+}
@@ -0,0 +1,420 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use chrono::{DateTime, Local, SubsecRound, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::{cmd::CMD, config, util::file_utils};
+
+use super::{repeat::{CatchUp, Repeat}, send_result::SendResult};
+
+/// a run of a recurring job is missed when the daemon gets to it this late
+const MISSED_AFTER: TimeDelta = TimeDelta::minutes(2);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// waiting for the daemon, a recurring job stays pending
+    Pending,
+    /// every receiver got the msg
+    Delivered,
+    /// some receivers got the msg, others did not
+    Partial,
+    /// no receiver got the msg
+    Failed,
+}
+
+impl std::fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s: &str = match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Delivered => "delivered",
+            JobStatus::Partial => "partial",
+            JobStatus::Failed => "failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// a msg waiting to be sent by `bark daemon`, the options are the ones it was scheduled with
+///
+/// `finished_at`, `error` and `results` tell about the last run, `due` about the next one
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Job {
+    id: u64,
+    due: DateTime<Local>,
+    status: JobStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repeat: Option<Repeat>,
+    #[serde(default)]
+    catch_up: CatchUp,
+    /// outcome of the last run of a recurring job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_status: Option<JobStatus>,
+    created_at: DateTime<Local>,
+    finished_at: Option<DateTime<Local>>,
+    /// why the job could not be sent at all, e.g. an alias deleted meanwhile
+    error: Option<String>,
+    #[serde(default)]
+    results: Vec<SendResult>,
+    options: CMD,
+}
+
+impl Job {
+    pub fn get_due(&self) -> DateTime<Local> {
+        self.due
+    }
+    pub fn get_status(&self) -> JobStatus {
+        self.status
+    }
+    pub fn get_last_status(&self) -> Option<JobStatus> {
+        self.last_status
+    }
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+    pub fn get_options(&self) -> &CMD {
+        &self.options
+    }
+
+    /// a run of a recurring job which should be dropped instead of sent late
+    pub fn is_missed(&self, now: DateTime<Local>) -> bool {
+        self.repeat.is_some() && self.catch_up == CatchUp::Skip && now - self.due > MISSED_AFTER
+    }
+
+    /// move a recurring job to its next run after `now`, a job without one is done
+    fn advance(&mut self, now: DateTime<Local>) {
+        match self.repeat.as_ref().and_then(|r| r.next(&self.due, &now)) {
+            Some(next) => {
+                self.due = next.trunc_subsecs(0);
+                self.status = JobStatus::Pending;
+            },
+            None => {
+                if let Some(last_status) = self.last_status.take() {
+                    self.status = last_status;
+                }
+            },
+        }
+    }
+}
+
+const HEAD_ID: &str = "ID";
+const HEAD_DUE: &str = "DUE";
+const HEAD_STATUS: &str = "STATUS";
+const HEAD_REPEAT: &str = "REPEAT";
+const HEAD_RECEIVER: &str = "RECEIVER";
+const HEAD_TITLE: &str = "TITLE";
+const DUE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %:z";
+
+/// scheduled jobs, kept in a file next to the run file
+#[derive(Serialize, Deserialize, Default)]
+pub struct JobQueue {
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    job: Vec<Job>,
+    #[serde(skip_serializing, skip_deserializing)]
+    path: String,
+}
+
+impl JobQueue {
+    pub fn new_empty(path: String) -> Self {
+        Self {
+            path,
+            ..Default::default()
+        }
+    }
+
+    pub fn new(queue: JobQueue, path: String) -> Self {
+        Self {
+            path,
+            ..queue
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_job(&self, id: u64) -> Option<&Job> {
+        self.job.iter().find(|j| j.id == id)
+    }
+
+    /// queue `options` to be sent at `due` and then as `repeat` says, returns the id of the new job
+    pub fn add(&mut self, due: DateTime<Local>, repeat: Option<Repeat>, catch_up: CatchUp, options: CMD, now: DateTime<Local>) -> u64 {
+        self.next_id = std::cmp::max(self.next_id, self.job.iter().map(|j| j.id).max().unwrap_or(0)) + 1;
+        self.job.push(Job {
+            id: self.next_id,
+            due: due.trunc_subsecs(0),
+            status: JobStatus::Pending,
+            repeat,
+            catch_up,
+            last_status: None,
+            created_at: now.trunc_subsecs(0),
+            finished_at: None,
+            error: None,
+            results: Vec::new(),
+            options,
+        });
+        self.next_id
+    }
+
+    /// drop a pending job, finished jobs are kept as a record
+    pub fn cancel(&mut self, id: u64) -> Result<(), String> {
+        match self.job.iter().position(|j| j.id == id) {
+            None => Err(format!("job {} not found", id)),
+            Some(i) if self.job[i].status != JobStatus::Pending => Err(format!("job {} is already {}", id, self.job[i].status)),
+            Some(i) => {
+                self.job.remove(i);
+                Ok(())
+            }
+        }
+    }
+
+    /// id and due time of pending jobs due at `now`, the earliest first
+    pub fn due(&self, now: DateTime<Local>) -> Vec<(u64, DateTime<Local>)> {
+        let mut due: Vec<&Job> = self.job.iter()
+            .filter(|j| j.status == JobStatus::Pending && j.due <= now)
+            .collect();
+        due.sort_by_key(|j| (j.due, j.id));
+        due.iter().map(|j| (j.id, j.due)).collect()
+    }
+
+    /// when the next pending job is due
+    pub fn next_due(&self) -> Option<DateTime<Local>> {
+        self.job.iter()
+            .filter(|j| j.status == JobStatus::Pending)
+            .map(|j| j.due)
+            .min()
+    }
+
+    /// record how sending a job went, a recurring job moves on to its next run
+    pub fn finish(&mut self, id: u64, outcome: Result<Vec<SendResult>, String>, now: DateTime<Local>) {
+        let Some(job) = self.job.iter_mut().find(|j| j.id == id) else {
+            return;
+        };
+        job.finished_at = Some(now.trunc_subsecs(0));
+        let status: JobStatus = match outcome {
+            Ok(results) => {
+                job.error = None;
+                job.results = results;
+                match SendResult::exit_code(&job.results) {
+                    config::EXIT_ALL_DELIVERED => JobStatus::Delivered,
+                    config::EXIT_PARTIAL_FAILURE => JobStatus::Partial,
+                    _ => JobStatus::Failed,
+                }
+            },
+            Err(e) => {
+                job.error = Some(e);
+                job.results = Vec::new();
+                JobStatus::Failed
+            }
+        };
+        if job.repeat.is_some() {
+            job.last_status = Some(status);
+        } else {
+            job.status = status;
+        }
+        job.advance(now);
+    }
+
+    /// drop the missed run of a recurring job without sending it
+    pub fn skip(&mut self, id: u64, now: DateTime<Local>) {
+        if let Some(job) = self.job.iter_mut().find(|j| j.id == id) {
+            job.advance(now);
+        }
+    }
+
+    pub fn pretty_print(&self) {
+        let mut jobs: Vec<&Job> = self.job.iter().collect();
+        jobs.sort_by_key(|j| (j.due, j.id));
+
+        let rows: Vec<[String; 6]> = jobs.iter().map(|j| [
+            j.id.to_string(),
+            j.due.format(DUE_FORMAT).to_string(),
+            match j.last_status {
+                Some(last_status) => format!("{} (last {})", j.status, last_status),
+                None => j.status.to_string(),
+            },
+            j.repeat.as_ref().map_or("-".to_string(), |r| r.to_string()),
+            j.options.receiver.join(","),
+            j.options.title.clone(),
+        ]).collect();
+        let heads: [&str; 6] = [HEAD_ID, HEAD_DUE, HEAD_STATUS, HEAD_REPEAT, HEAD_RECEIVER, HEAD_TITLE];
+        let widths: Vec<usize> = (0..heads.len()).map(|i| {
+            std::cmp::max(rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0), heads[i].len())
+        }).collect();
+
+        let print_row = |cells: [&str; 6]| {
+            let line: Vec<String> = cells.iter().zip(widths.iter()).map(|(c, w)| format!("{:<w$}", c)).collect();
+            println!("{}", line.join("    ").trim_end());
+        };
+        print_row(heads);
+        let dashes: Vec<String> = heads.iter().zip(widths.iter()).map(|(h, w)| format!("{:-<1$}", "", if h == &HEAD_TITLE { h.len() } else { *w })).collect();
+        print_row([&dashes[0], &dashes[1], &dashes[2], &dashes[3], &dashes[4], &dashes[5]]);
+        rows.iter().for_each(|r| print_row([&r[0], &r[1], &r[2], &r[3], &r[4], &r[5]]));
+    }
+
+    pub fn to_string(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(&self)
+    }
+
+    pub fn from_string(str: &str) -> Result<JobQueue, toml::de::Error> {
+        toml::from_str(str)
+    }
+
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        file_utils::write_queue_to_file(&self.path, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use clap::Parser;
+
+    use crate::module::send_result::Status;
+
+    use super::*;
+
+    fn options(title: &str) -> CMD {
+        CMD::try_parse_from(["bark", "-r", "alice,oncall", "-t", title, "-m", "hi \"there\"", "--delay", "60", "--retries", "2"]).unwrap()
+    }
+
+    fn result(status: Status) -> SendResult {
+        SendResult::new("alice", &"a".repeat(64), status, Some(200), None, None, 1)
+    }
+
+    #[test]
+    fn test_job_queue() {
+        let now: DateTime<Local> = Local::now().trunc_subsecs(0);
+        let mut queue = JobQueue::new_empty("bark.queue".to_string());
+        assert_eq!(1, queue.add(now + TimeDelta::minutes(2), None, CatchUp::Skip, options("later"), now));
+        assert_eq!(2, queue.add(now, None, CatchUp::Skip, options("now"), now));
+        assert_eq!(3, queue.add(now - TimeDelta::minutes(1), None, CatchUp::Skip, options("missed"), now));
+
+        assert_eq!(vec![(3, now - TimeDelta::minutes(1)), (2, now)], queue.due(now));
+        assert_eq!(Some(now - TimeDelta::minutes(1)), queue.next_due());
+
+        queue.finish(3, Ok(vec![result(Status::Delivered), result(Status::Failed)]), now);
+        queue.finish(2, Err("unknown receiver".to_string()), now);
+        assert_eq!(JobStatus::Partial, queue.get_job(3).unwrap().get_status());
+        assert_eq!(JobStatus::Failed, queue.get_job(2).unwrap().get_status());
+        assert_eq!(Some("unknown receiver"), queue.get_job(2).unwrap().get_error());
+        assert!(queue.due(now).is_empty());
+        assert_eq!(Some(now + TimeDelta::minutes(2)), queue.next_due());
+
+        assert!(queue.cancel(2).is_err());
+        assert!(queue.cancel(9).is_err());
+        queue.cancel(1).unwrap();
+        assert_eq!(None, queue.next_due());
+        // ids are not reused after a cancel
+        assert_eq!(4, queue.add(now, None, CatchUp::Skip, options("again"), now));
+
+        queue.pretty_print();
+    }
+
+    #[test]
+    fn test_job_queue_toml() {
+        let now: DateTime<Local> = Local::now().trunc_subsecs(0);
+        let mut queue = JobQueue::new_empty("bark.queue".to_string());
+        queue.add(now, None, CatchUp::Skip, options("first"), now);
+        queue.finish(1, Ok(vec![result(Status::Delivered)]), now);
+        queue.add(now + TimeDelta::hours(1), "every 2h".parse().ok(), CatchUp::FireOnce, options("second"), now);
+
+        let queue = JobQueue::from_string(&queue.to_string().unwrap()).unwrap();
+        let job: &Job = queue.get_job(2).unwrap();
+        assert_eq!(JobStatus::Pending, job.get_status());
+        assert_eq!("second", job.get_options().title);
+        assert_eq!(Some("hi \"there\"".to_string()), job.get_options().msg);
+        assert_eq!(vec!["alice".to_string(), "oncall".to_string()], job.get_options().receiver);
+        assert_eq!(2, job.get_options().retries);
+        // when to send is part of the job, not of its options
+        assert_eq!(None, job.get_options().delay);
+        assert_eq!(Some(now + TimeDelta::hours(1)), queue.next_due());
+        assert_eq!(JobStatus::Delivered, queue.get_job(1).unwrap().get_status());
+        assert_eq!(Some("every 2h".parse().unwrap()), job.repeat);
+        assert_eq!(CatchUp::FireOnce, job.catch_up);
+    }
+
+    #[test]
+    fn test_recurring_job() {
+        let now: DateTime<Local> = Local::now().trunc_subsecs(0);
+        let mut queue = JobQueue::new_empty("bark.queue".to_string());
+        let start: DateTime<Local> = now - TimeDelta::minutes(30);
+        queue.add(start, "every 1h".parse().ok(), CatchUp::Skip, options("skip"), now);
+        queue.add(start, "every 1h".parse().ok(), CatchUp::FireOnce, options("fire once"), now);
+        queue.add(now, "every 1h".parse().ok(), CatchUp::Skip, options("on time"), now);
+
+        assert!(queue.get_job(1).unwrap().is_missed(now));
+        assert!(!queue.get_job(2).unwrap().is_missed(now));
+        assert!(!queue.get_job(3).unwrap().is_missed(now + TimeDelta::seconds(10)));
+
+        queue.skip(1, now);
+        queue.finish(2, Ok(vec![result(Status::Delivered)]), now);
+        queue.finish(3, Err("unknown receiver".to_string()), now);
+        for (id, last_status) in [(1, None), (2, Some(JobStatus::Delivered)), (3, Some(JobStatus::Failed))] {
+            let job: &Job = queue.get_job(id).unwrap();
+            assert_eq!(JobStatus::Pending, job.get_status());
+            assert_eq!(last_status, job.last_status);
+        }
+        assert_eq!(start + TimeDelta::hours(1), queue.get_job(1).unwrap().get_due());
+        assert_eq!(start + TimeDelta::hours(1), queue.get_job(2).unwrap().get_due());
+        assert_eq!(now + TimeDelta::hours(1), queue.get_job(3).unwrap().get_due());
+        assert!(queue.due(now).is_empty());
+
+        queue.pretty_print();
+    }
+
+    #[test]
+    fn test_no_key_in_queue() {
+        let key: &str = "queued-key-of-24-chars!!";
+        let key_file: std::path::PathBuf = std::env::temp_dir().join(format!("bark_queue_key_test_{}", std::process::id()));
+        std::fs::write(&key_file, format!("{}\n", key)).unwrap();
+        let args = ["bark", "-r", "alice", "-m", "hi", "--delay", "60", "--aes192", "--gcm", "--iv", "queued-iv-12"];
+        let mut from_file: CMD = CMD::try_parse_from([&args[..], &["--key-file", key_file.to_str().unwrap()]].concat()).unwrap();
+        from_file.load_scheduled_key().unwrap();
+        assert_eq!(Some(key), from_file.key.as_deref());
+        let from_env: CMD = CMD::try_parse_from([&args[..], &["--key-env", "BARK_QUEUE_KEY_TEST"]].concat()).unwrap();
+        let passed: CMD = CMD::try_parse_from([&args[..], &["-k", key]].concat()).unwrap();
+
+        let now: DateTime<Local> = Local::now().trunc_subsecs(0);
+        let mut queue = JobQueue::new_empty("bark.queue".to_string());
+        queue.add(now, None, CatchUp::Skip, from_file, now);
+        queue.add(now, None, CatchUp::Skip, from_env, now);
+        queue.add(now, None, CatchUp::Skip, passed, now);
+        let content: String = queue.to_string().unwrap();
+        assert!(!content.contains(key) && !content.contains("queued-iv-12"));
+        assert!(content.contains(&format!("key_file = \"{}\"", key_file.display())));
+        assert!(content.contains("key_env = \"BARK_QUEUE_KEY_TEST\""));
+
+        // the key is read again when the job is sent
+        let queue: JobQueue = JobQueue::from_string(&content).unwrap();
+        let mut options: CMD = queue.get_job(1).unwrap().get_options().clone();
+        options.load_scheduled_key().unwrap();
+        assert_eq!(Some(key), options.key.as_deref());
+        assert!(queue.get_job(2).unwrap().get_options().clone().load_scheduled_key().is_err());
+        assert!(queue.get_job(3).unwrap().get_options().clone().load_scheduled_key().is_err());
+        std::fs::remove_file(&key_file).unwrap();
+        assert!(queue.get_job(1).unwrap().get_options().clone().load_scheduled_key().is_err());
+    }
+}
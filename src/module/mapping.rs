@@ -0,0 +1,104 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use serde::{Serialize, Deserialize};
+use toml::Table;
+
+/// receivers of webhook events about a repository or a user, like "owner/repo" or a GitHub login
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mapping {
+    name: String,
+    receivers: Vec<String>,
+    /// settings of the mapping this bark does not know, written back as they are
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+const HEAD_NAME: &str = "NAME";
+const HEAD_RECEIVERS: &str = "RECEIVERS";
+
+impl Mapping {
+    pub fn new(name: &str, receivers: Vec<String>) -> Mapping {
+        Mapping {
+            name: name.to_string(),
+            receivers,
+            unknown: Table::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_receivers(&self) -> &Vec<String> {
+        &self.receivers
+    }
+    pub fn unknown_keys(&self) -> Vec<String> {
+        self.unknown.keys().cloned().collect()
+    }
+
+    pub fn pretty_print(mappings: Vec<Mapping>) {
+        let max_name_len: usize = std::cmp::max(mappings.iter().map(|m| m.name.len()).max().unwrap_or(1), HEAD_NAME.len());
+
+        println!("{:<max_name_len$}    {}", HEAD_NAME, HEAD_RECEIVERS);
+        println!("{:-<max_name_len$}    {:-<2$}", "", "", HEAD_RECEIVERS.len());
+
+        mappings.iter().for_each(|m| {
+            println!("{:<max_name_len$}    {}", m.name, m.receivers.join(","));
+        });
+    }
+}
+
+impl std::str::FromStr for Mapping {
+    type Err = String;
+
+    /// the name may contain ":" itself, like a GitLab group "group:sub", the last one separates the receivers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, receivers) = s.rsplit_once(":").unwrap_or(("",""));
+        let receivers: Vec<String> = receivers.split(',')
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty())
+            .collect();
+        if name.trim().is_empty() || receivers.is_empty() {
+            return Err("Please input valid char like \"owner/repo:receiver1,receiver2\"".to_string());
+        }
+        Ok(Mapping::new(name.trim(), receivers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_mapping_from_str() {
+        let m = "66f94eae/bark:alice, oncall".parse::<Mapping>().unwrap();
+        assert_eq!("66f94eae/bark", m.get_name());
+        assert_eq!(&vec!["alice".to_string(), "oncall".to_string()], m.get_receivers());
+
+        assert!("octocat".parse::<Mapping>().is_err());
+        assert!("octocat:".parse::<Mapping>().is_err());
+        assert!(":alice".parse::<Mapping>().is_err());
+
+        Mapping::pretty_print(vec![m]);
+    }
+}
@@ -0,0 +1,130 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use clap::{ArgAction, Command};
+use serde::{Deserialize, Serialize};
+
+/// defaults of command line options like `title = "CI"` or `receiver = ["alias1", "alias2"]`, a `[profile.<name>]` table of the run file
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct Profile(toml::Table);
+
+// the config file is read before the profile is known
+const NOT_IN_PROFILE: [&str; 4] = ["config", "profile", "help", "version"];
+// an option passed on the command line also overrides the options of the profile it conflicts with
+const ALTERNATIVES: [&[&str]; 5] = [
+    &["aes128", "aes192", "aes256"],
+    &["cbc", "ecb", "gcm"],
+    &["key", "key_file", "key_env"],
+    &["delay", "at"],
+    &["msg", "msg_file"],
+];
+
+impl Profile {
+    /// command line args for the options of the profile, options which `passed` tells were given are left out
+    ///
+    /// returns the ids of the options taken from the profile and their args
+    pub fn to_args(&self, cmd: &Command, passed: impl Fn(&str) -> bool) -> Result<(Vec<String>, Vec<String>), String> {
+        let mut ids: Vec<String> = Vec::new();
+        let mut args: Vec<String> = Vec::new();
+        for (key, value) in &self.0 {
+            let id: String = key.replace('-', "_");
+            let Some(arg) = cmd.get_arguments()
+                .find(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some() && !NOT_IN_PROFILE.contains(&id.as_str())) else {
+                return Err(format!("unknown option {}", key));
+            };
+            let conflicts: &[&str] = ALTERNATIVES.iter().find(|ids| ids.contains(&id.as_str())).copied().unwrap_or_default();
+            if passed(&id) || conflicts.iter().any(|id| passed(id)) {
+                continue;
+            }
+
+            let long: &str = arg.get_long().unwrap();
+            match (arg.get_action(), value) {
+                (ArgAction::SetTrue, toml::Value::Boolean(true)) => args.push(format!("--{}", long)),
+                (ArgAction::SetTrue, toml::Value::Boolean(false)) => {},
+                (ArgAction::SetTrue, _) => return Err(format!("{} must be true or false", key)),
+                (ArgAction::Append, toml::Value::Array(values)) => {
+                    for value in values {
+                        args.push(format!("--{}={}", long, scalar(key, value)?));
+                    }
+                },
+                (_, value) => args.push(format!("--{}={}", long, scalar(key, value)?)),
+            }
+            ids.push(id);
+        }
+        Ok((ids, args))
+    }
+}
+
+fn scalar(key: &str, value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(d) => Ok(d.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => Err(format!("{} takes a single value", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::cmd::CMD;
+
+    fn profile(toml: &str) -> Profile {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_to_args() {
+        let cmd: Command = CMD::command();
+        let p: Profile = profile(r#"
+            title = "CI"
+            receiver = ["alias1", "alias2"]
+            auto-copy = false
+            aes256 = true
+            gcm = true
+            key = "-starts-with-a-dash-key!"
+            retries = 3
+        "#);
+
+        let (ids, args) = p.to_args(&cmd, |_| false).unwrap();
+        assert_eq!(vec!["aes256", "auto_copy", "gcm", "key", "receiver", "retries", "title"], ids);
+        assert_eq!(vec!["--aes256", "--auto-copy=false", "--gcm", "--key=-starts-with-a-dash-key!", "--receiver=alias1", "--receiver=alias2", "--retries=3", "--title=CI"], args);
+
+        // passing --aes128 and --title overrides the encryption type and the title of the profile
+        let (ids, _) = p.to_args(&cmd, |id| id == "aes128" || id == "title").unwrap();
+        assert_eq!(vec!["auto_copy", "gcm", "key", "receiver", "retries"], ids);
+    }
+
+    #[test]
+    fn test_to_args_invalid() {
+        let cmd: Command = CMD::command();
+        assert_eq!(Err("unknown option colour".to_string()), profile("colour = \"red\"").to_args(&cmd, |_| false));
+        assert_eq!(Err("unknown option config".to_string()), profile("config = \"x.toml\"").to_args(&cmd, |_| false));
+        assert_eq!(Err("gcm must be true or false".to_string()), profile("gcm = \"yes\"").to_args(&cmd, |_| false));
+        assert_eq!(Err("title takes a single value".to_string()), profile("title = [\"a\", \"b\"]").to_args(&cmd, |_| false));
+    }
+}
@@ -0,0 +1,253 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use bark_dev::msg;
+use serde_json::Value;
+
+use crate::cmd::CMD;
+
+/// body of a push without one, a msg can not be empty
+const NO_CONTENT: &str = "NoContent";
+
+/// a push in the format of the Bark server API
+///
+/// `/:key/:body`, `/:key/:title/:body`, `/:key/:title/:subtitle/:body` with options in the query or a form,
+/// or `POST /push` with the same names in json
+#[derive(Default, Debug, PartialEq)]
+pub struct PushRequest {
+    device_keys: Vec<String>,
+    title: Option<String>,
+    subtitle: Option<String>,
+    body: Option<String>,
+    level: Option<String>,
+    badge: Option<u64>,
+    sound: Option<String>,
+    icon: Option<String>,
+    group: Option<String>,
+    url: Option<String>,
+    copy: Option<String>,
+    archive: Option<bool>,
+    auto_copy: Option<bool>,
+    id: Option<String>,
+    delete: bool,
+}
+
+impl PushRequest {
+    /// decoded path segments like ["key", "title", "body"]
+    pub fn from_path(segments: &[String]) -> Result<PushRequest, String> {
+        let mut push: PushRequest = PushRequest::default();
+        match segments {
+            [key] => push.device_keys.push(key.clone()),
+            [key, body] => {
+                push.device_keys.push(key.clone());
+                push.body = Some(body.clone());
+            },
+            [key, title, body] => {
+                push.device_keys.push(key.clone());
+                push.title = Some(title.clone());
+                push.body = Some(body.clone());
+            },
+            [key, title, subtitle, body] => {
+                push.device_keys.push(key.clone());
+                push.title = Some(title.clone());
+                push.subtitle = Some(subtitle.clone());
+                push.body = Some(body.clone());
+            },
+            _ => return Err("path should be /:key[/:title[/:subtitle]]/:body".to_string()),
+        }
+        push.device_keys.retain(|k| !k.is_empty());
+        Ok(push)
+    }
+
+    /// a json object, numbers and booleans are taken as their text like the Bark server does
+    pub fn from_json(body: &[u8]) -> Result<PushRequest, String> {
+        let json: Value = serde_json::from_slice(body).map_err(|e| format!("invalid json: {}", e))?;
+        let Value::Object(fields) = json else {
+            return Err("invalid json: expected an object".to_string());
+        };
+
+        let mut push: PushRequest = PushRequest::default();
+        for (name, value) in fields {
+            match (name.as_str(), value) {
+                ("device_keys", Value::Array(keys)) => {
+                    for key in keys {
+                        push.set_param("device_key", &Self::json_text(key))?;
+                    }
+                },
+                (_, Value::Null) => {},
+                (name, value) => push.set_param(name, &Self::json_text(value))?,
+            }
+        }
+        Ok(push)
+    }
+
+    fn json_text(value: Value) -> String {
+        match value {
+            Value::String(s) => s,
+            value => value.to_string(),
+        }
+    }
+
+    /// set an option by its name in the Bark server API, unsupported options are ignored
+    pub fn set_param(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let text = || if value.is_empty() { None } else { Some(value.to_string()) };
+        match name {
+            "device_key" | "device_keys" => self.device_keys.extend(value.split(',').map(|k| k.trim().to_string()).filter(|k| !k.is_empty())),
+            "title" => self.title = text(),
+            "subtitle" => self.subtitle = text(),
+            "body" => self.body = text(),
+            "level" => {
+                if msg::Level::from_str(value).is_none() {
+                    return Err(format!("invalid level: {}", value));
+                }
+                self.level = text();
+            },
+            "badge" => self.badge = Some(value.parse().map_err(|_| format!("invalid badge: {}", value))?),
+            "sound" => {
+                // the app names sounds without extension
+                self.sound = text().map(|s| if s.contains('.') { s } else { format!("{}.caf", s) });
+            },
+            "icon" => self.icon = text(),
+            "group" => self.group = text(),
+            "url" => self.url = text(),
+            "copy" => self.copy = text(),
+            "isArchive" => self.archive = Some(Self::parse_bool(name, value)?),
+            "autoCopy" => self.auto_copy = Some(Self::parse_bool(name, value)?),
+            "id" => self.id = text(),
+            "delete" => self.delete = Self::parse_bool(name, value)?,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+        match value {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => Err(format!("invalid {}: {}, expected 1 or 0", name, value)),
+        }
+    }
+
+    /// the options to send this push with, options not in the request keep the ones of `base`
+    pub fn to_cmd(&self, base: &CMD) -> Result<CMD, String> {
+        if self.device_keys.is_empty() {
+            return Err("device key is empty".to_string());
+        }
+        if self.delete && self.id.is_none() {
+            return Err("id is required to delete a msg".to_string());
+        }
+        if self.id.as_ref().is_some_and(|id| id.len() >= 64) {
+            return Err("id must not exceed 64 bytes".to_string());
+        }
+
+        let mut cmd: CMD = base.clone();
+        cmd.receiver = self.device_keys.clone();
+        cmd.title = self.title.clone().unwrap_or(cmd.title);
+        let body: String = self.body.clone().unwrap_or(NO_CONTENT.to_string());
+        // the msg has no subtitle, it goes above the body
+        cmd.msg = Some(match &self.subtitle {
+            Some(subtitle) => format!("{}\n{}", subtitle, body),
+            None => body,
+        });
+        cmd.level = self.level.clone().unwrap_or(cmd.level);
+        cmd.badge = self.badge.or(cmd.badge);
+        cmd.sound = self.sound.clone().unwrap_or(cmd.sound);
+        cmd.icon = self.icon.clone().unwrap_or(cmd.icon);
+        cmd.group = self.group.clone().or(cmd.group);
+        cmd.url = self.url.clone().or(cmd.url);
+        cmd.copy = self.copy.clone().or(cmd.copy);
+        cmd.archive = self.archive.or(cmd.archive);
+        cmd.auto_copy = self.auto_copy.or(cmd.auto_copy);
+        cmd.id = self.id.clone().or(cmd.id);
+        cmd.delete = self.delete;
+        Ok(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn segments(path: &[&str]) -> Vec<String> {
+        path.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_path() {
+        let push = PushRequest::from_path(&segments(&["alice", "hello world"])).unwrap();
+        assert_eq!(vec!["alice".to_string()], push.device_keys);
+        assert_eq!((None, Some("hello world".to_string())), (push.title, push.body));
+
+        let push = PushRequest::from_path(&segments(&["alice", "title", "sub", "body"])).unwrap();
+        assert_eq!(Some("sub".to_string()), push.subtitle);
+
+        assert!(PushRequest::from_path(&segments(&["a", "b", "c", "d", "e"])).is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let push = PushRequest::from_json(br#"{"device_keys":["alice","oncall"],"title":"t","body":"b","badge":1,"isArchive":"1","sound":"minuet","level":"timeSensitive","action":"none","ciphertext":null}"#).unwrap();
+        assert_eq!(vec!["alice".to_string(), "oncall".to_string()], push.device_keys);
+        assert_eq!(Some(1), push.badge);
+        assert_eq!(Some(true), push.archive);
+        assert_eq!(Some("minuet.caf".to_string()), push.sound);
+        assert_eq!(Some("timeSensitive".to_string()), push.level);
+
+        assert!(PushRequest::from_json(br#"{"device_key":"alice","badge":"x"}"#).is_err());
+        assert!(PushRequest::from_json(br#"{"device_key":"alice","level":"loud"}"#).is_err());
+        assert!(PushRequest::from_json(br#"["alice"]"#).is_err());
+        assert!(PushRequest::from_json(b"alice").is_err());
+    }
+
+    #[test]
+    fn test_to_cmd() {
+        let base = CMD::try_parse_from(["bark", "-r", "nobody", "-m", "-", "--sound", "bell.caf", "--group", "server"]).unwrap();
+
+        let mut push = PushRequest::from_path(&segments(&["alice", "title", "sub", "body"])).unwrap();
+        push.set_param("url", "https://example.com").unwrap();
+        push.set_param("sound", "").unwrap();
+        let cmd = push.to_cmd(&base).unwrap();
+        assert_eq!(vec!["alice".to_string()], cmd.receiver);
+        assert_eq!("title", cmd.title);
+        assert_eq!(Some("sub\nbody".to_string()), cmd.msg);
+        assert_eq!("bell.caf", cmd.sound);
+        assert_eq!(Some("server".to_string()), cmd.group);
+        assert_eq!(Some("https://example.com".to_string()), cmd.url);
+
+        let cmd = PushRequest::from_path(&segments(&["alice"])).unwrap().to_cmd(&base).unwrap();
+        assert_eq!(("Notification".to_string(), Some(NO_CONTENT.to_string())), (cmd.title, cmd.msg));
+
+        // a -t before the subcommand is the default title
+        let titled = CMD::try_parse_from(["bark", "-r", "nobody", "-m", "-", "-t", "from cli"]).unwrap();
+        let cmd = PushRequest::from_path(&segments(&["alice", "body"])).unwrap().to_cmd(&titled).unwrap();
+        assert_eq!(("from cli".to_string(), Some("body".to_string())), (cmd.title, cmd.msg));
+        let cmd = PushRequest::from_path(&segments(&["alice", "title", "body"])).unwrap().to_cmd(&titled).unwrap();
+        assert_eq!("title", cmd.title);
+
+        assert!(PushRequest::from_path(&segments(&[""])).unwrap().to_cmd(&base).is_err());
+        let mut push = PushRequest::from_path(&segments(&["alice"])).unwrap();
+        push.set_param("delete", "1").unwrap();
+        assert!(push.to_cmd(&base).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use chrono::{DateTime, Days, LocalResult, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// how far ahead to look for a cron match, covers a Feb 29 on a given weekday
+const CRON_SEARCH_DAYS: u64 = 366 * 28;
+
+/// what to do with a run of a recurring job that was missed, e.g. while the machine was asleep
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CatchUp {
+    /// drop missed runs and wait for the next one
+    #[default]
+    Skip,
+    /// send once for all missed runs, then go on as scheduled
+    FireOnce,
+}
+
+/// how a scheduled job repeats, kept as the expression it was written in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Repeat {
+    expr: String,
+    rule: Rule,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Rule {
+    Every(TimeDelta),
+    Cron(Cron),
+}
+
+impl Repeat {
+    /// the first run at or after `start`
+    pub fn first<Tz: TimeZone>(&self, start: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match &self.rule {
+            Rule::Every(_) => Some(start.clone()),
+            Rule::Cron(cron) => cron.next_after(&(start.clone() - TimeDelta::minutes(1))),
+        }
+    }
+
+    /// the first run after `now`, runs of an interval stay aligned to `due`
+    pub fn next<Tz: TimeZone>(&self, due: &DateTime<Tz>, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match &self.rule {
+            Rule::Every(interval) => {
+                if due > now {
+                    return Some(due.clone());
+                }
+                let passed: i64 = (now.clone() - due.clone()).num_seconds() / interval.num_seconds();
+                Some(due.clone() + *interval * (passed as i32 + 1))
+            },
+            Rule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    /// "every 2h", "every 1h30m", "every day"
+    fn parse_every(s: &str) -> Result<TimeDelta, String> {
+        let s: &str = s.trim();
+        let err = || format!("invalid interval \"{}\", use a form like \"every 30m\", \"every 2h\", \"every 1d\" or \"every week\"", s);
+        let spec: String = s.split_whitespace().collect::<Vec<&str>>().join("");
+        let spec: String = if spec.starts_with(|c: char| c.is_ascii_digit()) { spec } else { format!("1{}", spec) };
+
+        let mut total: TimeDelta = TimeDelta::zero();
+        let mut rest: &str = &spec;
+        while !rest.is_empty() {
+            let digits: usize = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let letters: usize = rest[digits..].find(|c: char| c.is_ascii_digit()).map_or(rest.len(), |i| digits + i);
+            let n: i64 = rest[..digits].parse().map_err(|_| err())?;
+            let unit: TimeDelta = match &rest[digits..letters] {
+                "m" | "min" | "mins" | "minute" | "minutes" => TimeDelta::minutes(1),
+                "h" | "hour" | "hours" => TimeDelta::hours(1),
+                "d" | "day" | "days" => TimeDelta::days(1),
+                "w" | "week" | "weeks" => TimeDelta::weeks(1),
+                _ => return Err(err()),
+            };
+            total = n.checked_mul(unit.num_seconds())
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|d| total.checked_add(&d))
+                .ok_or_else(err)?;
+            rest = &rest[letters..];
+        }
+        if total < TimeDelta::minutes(1) {
+            return Err(format!("interval \"{}\" must be at least 1 minute", s));
+        }
+        Ok(total)
+    }
+}
+
+impl std::str::FromStr for Repeat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expr: String = s.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase();
+        let rule: Rule = match expr.strip_prefix("every") {
+            Some(every) if every.is_empty() || every.starts_with(' ') => Rule::Every(Self::parse_every(every)?),
+            _ => Rule::Cron(expr.parse()?),
+        };
+        Ok(Repeat { expr, rule })
+    }
+}
+
+impl TryFrom<String> for Repeat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Repeat> for String {
+    fn from(repeat: Repeat) -> String {
+        repeat.expr
+    }
+}
+
+impl std::fmt::Display for Repeat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+/// standard 5 field cron expression "minute hour day-of-month month day-of-week" in local time
+///
+/// like cron, when both day fields are restricted a day matching either of them matches,
+/// a day field starting with "*" or covering its whole range like "1-31" is not restricted
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const ALL_DAYS: u64 = ((1 << 32) - 1) & !1;
+const ALL_WEEKDAYS: u64 = (1 << 7) - 1;
+
+impl Cron {
+    /// the first minute matching after `after`, a local time skipped by daylight saving is not run
+    fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let start: NaiveDateTime = after.naive_local().with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let mut date: NaiveDate = start.date();
+        for _ in 0..CRON_SEARCH_DAYS {
+            if self.matches_date(date) {
+                for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                    for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                        let naive: NaiveDateTime = date.and_hms_opt(hour, minute, 0)?;
+                        if naive < start {
+                            continue;
+                        }
+                        let dt: DateTime<Tz> = match after.timezone().from_local_datetime(&naive) {
+                            LocalResult::Single(dt) => dt,
+                            LocalResult::Ambiguous(earliest, _) => earliest,
+                            LocalResult::None => continue,
+                        };
+                        if dt > *after {
+                            return Some(dt);
+                        }
+                    }
+                }
+            }
+            date = date.checked_add_days(Days::new(1))?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        use chrono::Datelike;
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day: bool = self.days & (1 << date.day()) != 0;
+        let weekday: bool = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// a field like "*", "*/15", "1-5", "mon-fri", "0,30" as a bit set
+    fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+        let value = |s: &str| -> Result<u32, String> {
+            let v: u32 = match names.iter().position(|n| *n == s) {
+                Some(i) => i as u32 + if min == 1 { 1 } else { 0 },
+                None => s.parse().map_err(|_| format!("invalid cron value \"{}\"", s))?,
+            };
+            Ok(v)
+        };
+
+        let mut bits: u64 = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|_| format!("invalid cron step \"{}\"", part))?),
+                None => (part, 1),
+            };
+            let (from, to) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((from, to)) => (value(from)?, value(to)?),
+                    None => {
+                        let v: u32 = value(range)?;
+                        (v, if part.contains('/') { max } else { v })
+                    },
+                },
+            };
+            if step == 0 || from < min || to > max || from > to {
+                return Err(format!("cron field \"{}\" is out of range [{}..{}]", part, min, max));
+            }
+            (from..=to).step_by(step as usize).for_each(|v| bits |= 1 << v);
+        }
+        Ok(bits)
+    }
+}
+
+impl std::str::FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expr: &str = match s {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            _ => s,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("invalid repeat \"{}\", use a cron expression like \"55 9 * * mon-fri\" or a form like \"every 2h\"", s));
+        }
+
+        let mut weekdays: u64 = Self::parse_field(fields[4], 0, 7, &WEEKDAY_NAMES)?;
+        // both 0 and 7 are sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let days: u64 = Self::parse_field(fields[2], 1, 31, &[])?;
+        Ok(Cron {
+            minutes: Self::parse_field(fields[0], 0, 59, &[])?,
+            hours: Self::parse_field(fields[1], 0, 23, &[])?,
+            days,
+            months: Self::parse_field(fields[3], 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: fields[2].starts_with('*') || days == ALL_DAYS,
+            any_weekday: fields[4].starts_with('*') || weekdays == ALL_WEEKDAYS,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::*;
+
+    fn at(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn next(repeat: &str, due: &str, now: &str) -> String {
+        repeat.parse::<Repeat>().unwrap().next(&at(due), &at(now)).unwrap().to_rfc3339()
+    }
+
+    #[test]
+    fn test_parse_repeat() {
+        assert_eq!("every 2h", "Every  2h".parse::<Repeat>().unwrap().to_string());
+        assert_eq!(Rule::Every(TimeDelta::minutes(90)), "every 1h 30m".parse::<Repeat>().unwrap().rule);
+        assert_eq!(Rule::Every(TimeDelta::days(1)), "every day".parse::<Repeat>().unwrap().rule);
+        assert_eq!(Rule::Every(TimeDelta::weeks(2)), "every 2 weeks".parse::<Repeat>().unwrap().rule);
+        assert!("every 0m".parse::<Repeat>().is_err());
+        assert!("every 2x".parse::<Repeat>().is_err());
+        assert!("every".parse::<Repeat>().is_err());
+
+        assert!("55 9 * * mon-fri".parse::<Repeat>().is_ok());
+        assert!("*/15 * * * *".parse::<Repeat>().is_ok());
+        assert!("@daily".parse::<Repeat>().is_ok());
+        assert!("60 * * * *".parse::<Repeat>().is_err());
+        assert!("* * * *".parse::<Repeat>().is_err());
+        assert!("5-1 * * * *".parse::<Repeat>().is_err());
+        assert!("*/0 * * * *".parse::<Repeat>().is_err());
+    }
+
+    #[test]
+    fn test_next_every() {
+        // runs stay aligned to the first due time, missed runs are not replayed
+        assert_eq!("2026-10-18T12:00:00+08:00", next("every 2h", "2026-10-18T10:00:00+08:00", "2026-10-18T10:00:00+08:00"));
+        assert_eq!("2026-10-18T16:00:00+08:00", next("every 2h", "2026-10-18T10:00:00+08:00", "2026-10-18T15:59:00+08:00"));
+        assert_eq!("2026-10-18T11:00:00+08:00", next("every 2h", "2026-10-18T11:00:00+08:00", "2026-10-18T10:00:00+08:00"));
+    }
+
+    #[test]
+    fn test_next_cron() {
+        let weekdays = "55 9 * * 1-5";
+        // saturday morning -> monday
+        assert_eq!("2026-10-19T09:55:00+08:00", next(weekdays, "2026-10-16T09:55:00+08:00", "2026-10-17T08:00:00+08:00"));
+        assert_eq!("2026-10-20T09:55:00+08:00", next(weekdays, "2026-10-19T09:55:00+08:00", "2026-10-19T09:55:00+08:00"));
+        assert_eq!("2026-10-18T10:15:00+08:00", next("*/15 * * * *", "2026-10-18T10:00:00+08:00", "2026-10-18T10:00:01+08:00"));
+        assert_eq!("2026-11-01T00:00:00+08:00", next("@monthly", "2026-10-01T00:00:00+08:00", "2026-10-18T10:00:00+08:00"));
+        // both day fields restricted: the 13th or any friday
+        assert_eq!("2026-10-23T00:00:00+08:00", next("0 0 13 * fri", "2026-10-13T00:00:00+08:00", "2026-10-18T10:00:00+08:00"));
+        // a day field covering its whole range is not restricted: only mondays
+        assert_eq!("2026-10-19T09:00:00+08:00", next("0 9 */1 * 1", "2026-10-12T09:00:00+08:00", "2026-10-13T10:00:00+08:00"));
+        assert_eq!("2026-10-19T09:00:00+08:00", next("0 9 1-31 * mon", "2026-10-12T09:00:00+08:00", "2026-10-13T10:00:00+08:00"));
+        assert_eq!("2026-11-01T09:00:00+08:00", next("0 9 1 * 0-7", "2026-10-01T09:00:00+08:00", "2026-10-13T10:00:00+08:00"));
+        assert_eq!("2026-10-18T00:00:00+08:00", next("0 0 * * 7", "2026-10-11T00:00:00+08:00", "2026-10-17T10:00:00+08:00"));
+        assert_eq!("2028-02-29T00:00:00+08:00", next("0 0 29 feb *", "2024-02-29T00:00:00+08:00", "2026-10-18T10:00:00+08:00"));
+        assert!("0 0 30 feb *".parse::<Repeat>().unwrap().first(&at("2026-10-18T10:00:00+08:00")).is_none());
+        assert_eq!("2026-10-18T10:00:00+08:00", "0 10 * * *".parse::<Repeat>().unwrap().first(&at("2026-10-18T10:00:00+08:00")).unwrap().to_rfc3339());
+    }
+
+    #[test]
+    fn test_next_cron_dst() {
+        use chrono_tz::America::New_York;
+        let repeat: Repeat = "30 2 * * *".parse().unwrap();
+        // 2026-03-08 02:30 does not exist in New York
+        let now = New_York.with_ymd_and_hms(2026, 3, 7, 12, 0, 0).unwrap();
+        assert_eq!("2026-03-09T02:30:00-04:00", repeat.next(&now, &now).unwrap().to_rfc3339());
+    }
+}
@@ -0,0 +1,133 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use std::time::Duration;
+
+/// the longest wait before a retry, jitter excluded, the doubling stops there
+pub const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// how `Sender` resends to devices failed with a retriable error
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+    jitter: f64,
+    max_time: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    /// no retry at all
+    fn default() -> Self {
+        RetryPolicy::new(0, Duration::from_secs(1), 0.0, None)
+    }
+}
+
+impl RetryPolicy {
+    /// - `retries`: resend at most this many times
+    /// - `backoff`: wait before the first retry, doubled for every following one up to `MAX_BACKOFF`
+    /// - `jitter`: add a random part of up to `jitter` times the wait, clamped into [0, 1]
+    /// - `max_time`: stop retrying once the next retry would start later than this after the first attempt
+    pub fn new(retries: u32, backoff: Duration, jitter: f64, max_time: Option<Duration>) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            backoff,
+            jitter: jitter.clamp(0.0, 1.0),
+            max_time,
+        }
+    }
+
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// wait before retry number `retry` (1 based), jitter excluded
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1))).min(MAX_BACKOFF)
+    }
+
+    /// wait before retry number `retry` (1 based), jitter included
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff: Duration = self.backoff(retry);
+        if self.jitter == 0.0 {
+            return backoff;
+        }
+        let mut random: [u8; 8] = [0u8; 8];
+        let fraction: f64 = match openssl::rand::rand_bytes(&mut random) {
+            Ok(_) => u64::from_le_bytes(random) as f64 / u64::MAX as f64,
+            Err(_) => 0.5,
+        };
+        backoff.saturating_add(backoff.mul_f64(self.jitter * fraction))
+    }
+
+    /// whether retry number `retry` is allowed when `elapsed` has passed since the first attempt and it waits `delay`
+    pub fn allows(&self, retry: u32, elapsed: Duration, delay: Duration) -> bool {
+        if retry > self.retries {
+            return false;
+        }
+        match self.max_time {
+            Some(max_time) => elapsed.saturating_add(delay) <= max_time,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(500), 0.0, None);
+        assert_eq!(Duration::from_millis(500), policy.backoff(1));
+        assert_eq!(Duration::from_millis(1000), policy.backoff(2));
+        assert_eq!(Duration::from_millis(4000), policy.backoff(4));
+        assert_eq!(policy.backoff(3), policy.delay(3));
+
+        let policy = RetryPolicy::new(u32::MAX, Duration::from_secs(3000), 1.0, Some(Duration::MAX));
+        assert_eq!(Duration::from_secs(3000), policy.backoff(1));
+        assert_eq!(MAX_BACKOFF, policy.backoff(2));
+        assert_eq!(MAX_BACKOFF, policy.backoff(u32::MAX));
+        assert!(policy.delay(u32::MAX) <= MAX_BACKOFF * 2);
+        assert!(policy.allows(u32::MAX, Duration::MAX, MAX_BACKOFF));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(2), 0.5, None);
+        for _ in 0..20 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let policy = RetryPolicy::new(2, Duration::from_secs(1), 0.0, Some(Duration::from_secs(10)));
+        assert!(policy.allows(1, Duration::from_secs(0), Duration::from_secs(1)));
+        assert!(policy.allows(2, Duration::from_secs(5), Duration::from_secs(5)));
+        assert!(!policy.allows(2, Duration::from_secs(6), Duration::from_secs(5)));
+        assert!(!policy.allows(3, Duration::from_secs(0), Duration::from_secs(1)));
+
+        assert!(!RetryPolicy::default().allows(1, Duration::from_secs(0), Duration::from_secs(0)));
+    }
+}
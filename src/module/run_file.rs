@@ -21,28 +21,63 @@
 // SOFTWARE.
 
 
-use std::{collections::HashMap, process::exit};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::File, path::Path, process::exit};
 
-use crate::util::file_utils;
+use crate::{config, util::file_utils};
 
-use super::{token::Token, user_info::UserInfo};
+use super::{apns_config::ApnsConfig, group_info::GroupInfo, mapping::Mapping, profile::Profile, template::Template, user_info::UserInfo};
 use serde::{Serialize, Deserialize};
+use toml::{Table, Value};
+
+/// version of the layout this bark reads and writes, a file without `schema_version` is version 0
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// `MIGRATIONS[n]` upgrades the table of a version n run file to version n + 1
+const MIGRATIONS: [fn(&mut Table); SCHEMA_VERSION as usize] = [
+    // version 0 is every file written before the version was, the sections added until then
+    // are all optional, so it reads as version 1 as it is
+    |_| {},
+    // the cached tokens moved into the token cache, they are signed again on the next send
+    |table| {
+        table.remove("token");
+        table.remove("credential_token");
+    },
+];
 
 #[derive(Serialize, Deserialize)]
 pub struct RunFile {
+    #[serde(default)]
+    schema_version: u32,
     user_info: Option<Vec<UserInfo>>,
-    token: Option<Token>,
+    group: Option<Vec<GroupInfo>>,
+    mapping: Option<Vec<Mapping>>,
+    template: Option<Vec<Template>>,
+    profile: Option<BTreeMap<String, Profile>>,
+    apns: Option<ApnsConfig>,
+    /// tables and keys this bark does not know, like the ones of a newer version, written back as they are
+    #[serde(flatten)]
+    unknown: Table,
+    #[serde(skip_serializing, skip_deserializing)]
+    path: String,
+    /// the file changed into one which does not parse since it was read, it is not written over
     #[serde(skip_serializing, skip_deserializing)]
-    path: String
+    broken: bool,
 }
 
 impl RunFile {
     
     pub fn new_empty(path: String) -> Self {
         Self {
+            schema_version: SCHEMA_VERSION,
             user_info: None,
-            token: None,
-            path: path
+            group: None,
+            mapping: None,
+            template: None,
+            profile: None,
+            apns: None,
+            unknown: Table::new(),
+            path,
+            broken: false,
         }
     }
 
@@ -52,21 +87,8 @@ impl RunFile {
             ..rf
         }
     }
-    pub fn get_token(&self) -> Option<Token> {
-        match self.token.clone() {
-            Some(t) => Some(t),
-            None => None
-        }
-    }
-
-    pub fn set_token(&mut self, time_stamp: u64, token: &str) {
-        if let Some(t) = self.token.as_mut() {
-            if t.get_refresh_at() == time_stamp {
-                return;
-            }
-        }
-        self.token = Some(Token::new(time_stamp, token));
-        self.save();
+    pub fn get_apns(&self) -> ApnsConfig {
+        self.apns.clone().unwrap_or_default()
     }
 
     pub fn get_user_info(&self) -> Vec<UserInfo> {
@@ -79,14 +101,23 @@ impl RunFile {
     pub fn get_user_info_by_name(&self, name: &str) -> Option<UserInfo> {
         self.get_user_info().iter().find(|u| u.get_nick_name() == name).cloned()
     }
+    /// the first user with the device token, for a receiver given as a raw device token
+    pub fn get_user_info_by_device_token(&self, device_token: &str) -> Option<UserInfo> {
+        self.get_user_info().iter().find(|u| u.get_device_token() == device_token).cloned()
+    }
     pub fn add_user_info(&mut self, user_infos: Vec<&UserInfo>) {
+        let _lock: Option<File> = self.lock();
         let mut users: Vec<UserInfo> = self.get_user_info();
         let mut tmp_users: Vec<UserInfo> = Vec::<UserInfo>::new();
 
         for user in user_infos {
             if users.iter().any(|u| u.get_nick_name() == user.get_nick_name()) {
                 eprintln!("user {} already exists", user.get_nick_name());
-                exit(0x0100);
+                exit(config::EXIT_INVALID_INPUT);
+            }
+            if self.get_group_info_by_name(user.get_nick_name()).is_some() {
+                eprintln!("{} is already used as group name", user.get_nick_name());
+                exit(config::EXIT_INVALID_INPUT);
             }
             tmp_users.push(user.clone());
         }
@@ -97,41 +128,377 @@ impl RunFile {
         self.save();
     }
     pub fn remove_user_info(&mut self, names: Vec<&String>) {
+        let _lock: Option<File> = self.lock();
         let mut users: Vec<UserInfo> = self.get_user_info();
         for name in names {
             users.iter().position(|u| u.get_nick_name() == name)
-                .and_then(|i| {
-                    users.remove(i);
-                    Some(i)
-                });
+                .map(|i| users.remove(i));
         }
         self.user_info = Some(users);
         self.save();
     }
+
+    pub fn get_group_info(&self) -> Vec<GroupInfo> {
+        if let Some(group) = &self.group {
+            group.clone()
+        } else {
+            Vec::new()
+        }
+    }
+    pub fn get_group_info_by_name(&self, name: &str) -> Option<GroupInfo> {
+        self.get_group_info().iter().find(|g| g.get_name() == name).cloned()
+    }
+    pub fn add_group_info(&mut self, group_infos: Vec<&GroupInfo>) {
+        let _lock: Option<File> = self.lock();
+        let mut groups: Vec<GroupInfo> = self.get_group_info();
+        let mut tmp_groups: Vec<GroupInfo> = Vec::<GroupInfo>::new();
+
+        for group in group_infos {
+            if groups.iter().any(|g| g.get_name() == group.get_name()) {
+                eprintln!("group {} already exists", group.get_name());
+                exit(config::EXIT_INVALID_INPUT);
+            }
+            if self.get_user_info_by_name(group.get_name()).is_some() {
+                eprintln!("{} is already used as user alias", group.get_name());
+                exit(config::EXIT_INVALID_INPUT);
+            }
+            tmp_groups.push(group.clone());
+        }
+
+        groups.append(&mut tmp_groups);
+        self.group = Some(groups);
+
+        self.save();
+    }
+    pub fn remove_group_info(&mut self, names: Vec<&String>) {
+        let _lock: Option<File> = self.lock();
+        let mut groups: Vec<GroupInfo> = self.get_group_info();
+        for name in names {
+            groups.iter().position(|g| g.get_name() == name)
+                .map(|i| groups.remove(i));
+        }
+        self.group = Some(groups);
+        self.save();
+    }
+
+    pub fn get_mapping(&self) -> Vec<Mapping> {
+        if let Some(mapping) = &self.mapping {
+            mapping.clone()
+        } else {
+            Vec::new()
+        }
+    }
+    pub fn get_mapping_by_name(&self, name: &str) -> Option<Mapping> {
+        self.get_mapping().iter().find(|m| m.get_name() == name).cloned()
+    }
+    pub fn add_mapping(&mut self, mappings: Vec<&Mapping>) {
+        let _lock: Option<File> = self.lock();
+        let mut current: Vec<Mapping> = self.get_mapping();
+        for mapping in mappings {
+            if current.iter().any(|m| m.get_name() == mapping.get_name()) {
+                eprintln!("mapping {} already exists", mapping.get_name());
+                exit(config::EXIT_INVALID_INPUT);
+            }
+            current.push(mapping.clone());
+        }
+        self.mapping = Some(current);
+        self.save();
+    }
+    pub fn remove_mapping(&mut self, names: Vec<&String>) {
+        let _lock: Option<File> = self.lock();
+        let mut mappings: Vec<Mapping> = self.get_mapping();
+        for name in names {
+            mappings.iter().position(|m| m.get_name() == name)
+                .map(|i| mappings.remove(i));
+        }
+        self.mapping = Some(mappings);
+        self.save();
+    }
+
+    pub fn get_template(&self) -> Vec<Template> {
+        if let Some(template) = &self.template {
+            template.clone()
+        } else {
+            Vec::new()
+        }
+    }
+    pub fn get_template_by_name(&self, name: &str) -> Option<Template> {
+        self.get_template().into_iter().find(|t| t.get_name() == name)
+    }
+    pub fn add_template(&mut self, template: &Template) {
+        let _lock: Option<File> = self.lock();
+        let mut templates: Vec<Template> = self.get_template();
+        if templates.iter().any(|t| t.get_name() == template.get_name()) {
+            eprintln!("template {} already exists", template.get_name());
+            exit(config::EXIT_INVALID_INPUT);
+        }
+        templates.push(template.clone());
+        self.template = Some(templates);
+        self.save();
+    }
+    pub fn remove_template(&mut self, names: Vec<&String>) {
+        let _lock: Option<File> = self.lock();
+        let mut templates: Vec<Template> = self.get_template();
+        templates.retain(|t| !names.iter().any(|name| *name == t.get_name()));
+        self.template = Some(templates);
+        self.save();
+    }
+
+    pub fn get_profile(&self, name: &str) -> Option<Profile> {
+        self.profile.as_ref().and_then(|profiles| profiles.get(name)).cloned()
+    }
+
+    /// receivers of the mappings of `names`, a name without mapping is taken as is when it is an alias or a group
+    pub fn mapped_receivers(&self, names: &[String]) -> Vec<String> {
+        let mut receivers: Vec<String> = Vec::new();
+        for name in names {
+            let mapped: Vec<String> = match self.get_mapping_by_name(name) {
+                Some(mapping) => mapping.get_receivers().clone(),
+                None if self.get_user_info_by_name(name).is_some() || self.get_group_info_by_name(name).is_some() => vec![name.clone()],
+                None => Vec::new(),
+            };
+            for receiver in mapped {
+                if !receivers.contains(&receiver) {
+                    receivers.push(receiver);
+                }
+            }
+        }
+        receivers
+    }
     
+    /// what is wrong with the version, users, groups, mappings, templates and apns credential, empty if nothing
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+        if self.schema_version > SCHEMA_VERSION {
+            problems.push(format!("schema_version {} is newer than {} this bark knows, what it does not know is kept as it is", self.schema_version, SCHEMA_VERSION));
+        }
+        let users: Vec<UserInfo> = self.get_user_info();
+        let groups: Vec<GroupInfo> = self.get_group_info();
+
+        Self::duplicates("user", users.iter().map(|u| u.get_nick_name()), &mut problems);
+        Self::duplicates("group", groups.iter().map(|g| g.get_name()), &mut problems);
+        Self::duplicates("mapping", self.get_mapping().iter().map(|m| m.get_name()), &mut problems);
+        Self::duplicates("template", self.get_template().iter().map(|t| t.get_name()), &mut problems);
+
+        for user in &users {
+            if !Self::is_device_token(user.get_device_token()) {
+                problems.push(format!("device token of user {} is not 64 hex characters", user.get_nick_name()));
+            }
+            if let Some(Err(e)) = user.get_encryption().map(|e| e.validate()) {
+                problems.push(format!("encryption of user {}: {}", user.get_nick_name(), e));
+            }
+        }
+
+        let known = |name: &str| users.iter().any(|u| u.get_nick_name() == name)
+            || groups.iter().any(|g| g.get_name() == name)
+            || Self::is_device_token(name);
+        for group in &groups {
+            if users.iter().any(|u| u.get_nick_name() == group.get_name()) {
+                problems.push(format!("{} is both a user and a group", group.get_name()));
+            }
+            for member in group.get_members().iter().filter(|member| !known(member)) {
+                problems.push(format!("member {} of group {} is neither a user, a group nor a device token", member, group.get_name()));
+            }
+            if let Err(e) = self.translate_to_real_devices(&vec![group.get_name().to_string()]) {
+                problems.push(e);
+            }
+        }
+        for mapping in self.get_mapping() {
+            for receiver in mapping.get_receivers().iter().filter(|receiver| !known(receiver)) {
+                problems.push(format!("receiver {} of mapping {} is neither a user, a group nor a device token", receiver, mapping.get_name()));
+            }
+        }
+        for template in self.get_template() {
+            if let Err(e) = template.validate() {
+                problems.push(format!("template {}: {}", template.get_name(), e));
+            }
+        }
+        if let Err(e) = self.get_apns().credential() {
+            problems.push(e);
+        }
+
+        problems.dedup();
+        problems
+    }
+
+    /// keep the first of the users, groups, mappings and templates sharing a name, and drop groups named like a user
+    ///
+    /// return what was dropped
+    pub fn dedupe(&mut self) -> Vec<String> {
+        let mut dropped: Vec<String> = Vec::new();
+        self.user_info = Self::dedupe_by(self.user_info.take(), "user", |u| u.get_nick_name(), &mut dropped);
+        self.group = Self::dedupe_by(self.group.take(), "group", |g| g.get_name(), &mut dropped);
+        self.mapping = Self::dedupe_by(self.mapping.take(), "mapping", |m| m.get_name(), &mut dropped);
+        self.template = Self::dedupe_by(self.template.take(), "template", |t| t.get_name(), &mut dropped);
+
+        let users: Vec<UserInfo> = self.get_user_info();
+        if let Some(groups) = self.group.as_mut() {
+            groups.retain(|g| {
+                let is_user: bool = users.iter().any(|u| u.get_nick_name() == g.get_name());
+                if is_user {
+                    dropped.push(format!("group {}, it is named like a user", g.get_name()));
+                }
+                !is_user
+            });
+        }
+        dropped
+    }
+
+    fn duplicates<'a>(kind: &str, names: impl Iterator<Item = &'a str>, problems: &mut Vec<String>) {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for name in names {
+            match counts.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((name, 1)),
+            }
+        }
+        for (name, count) in counts.into_iter().filter(|(_, count)| *count > 1) {
+            problems.push(format!("{} {} is defined {} times", kind, name, count));
+        }
+    }
+
+    fn dedupe_by<T>(items: Option<Vec<T>>, kind: &str, name: impl Fn(&T) -> &str, dropped: &mut Vec<String>) -> Option<Vec<T>> {
+        let mut kept: Vec<T> = Vec::new();
+        for item in items? {
+            if kept.iter().any(|k| name(k) == name(&item)) {
+                dropped.push(format!("{} {}, the first one with the name is kept", kind, name(&item)));
+            } else {
+                kept.push(item);
+            }
+        }
+        Some(kept)
+    }
+
+    fn is_device_token(token: &str) -> bool {
+        token.len() == 64 && token.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
     pub fn to_string(&self) -> Result<String, toml::ser::Error> {
         toml::to_string_pretty(&self)
     }
 
+    /// parse a run file of any version, an older one is upgraded to `SCHEMA_VERSION`
     pub fn from_string(str: &str) -> Result<RunFile, toml::de::Error> {
-        toml::from_str(str)
+        let mut table: Table = toml::from_str(str)?;
+        if !Self::migrate(&mut table) {
+            // parsed from the text, so errors tell their line and column
+            return toml::from_str(str);
+        }
+        Value::Table(table).try_into()
+    }
+
+    /// upgrade the table of an older run file to `SCHEMA_VERSION` step by step
+    ///
+    /// return false if there was nothing to upgrade, a newer or malformed `schema_version` is left as it is
+    pub fn migrate(table: &mut Table) -> bool {
+        let version: u32 = match table.get("schema_version") {
+            None => 0,
+            Some(version) => match version.as_integer().and_then(|v| u32::try_from(v).ok()) {
+                Some(version) => version,
+                None => return false,
+            },
+        };
+        if version >= SCHEMA_VERSION {
+            return false;
+        }
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(table);
+        }
+        table.insert("schema_version".to_string(), Value::Integer(SCHEMA_VERSION.into()));
+        true
+    }
+
+    /// dotted names of the keys kept without being known, like "future" or "user_info.0.sound"
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.unknown.keys().cloned().collect();
+        for (i, user) in self.get_user_info().iter().enumerate() {
+            keys.extend(user.unknown_keys().into_iter().map(|key| format!("user_info.{}.{}", i, key)));
+        }
+        for (i, group) in self.get_group_info().iter().enumerate() {
+            keys.extend(group.unknown_keys().into_iter().map(|key| format!("group.{}.{}", i, key)));
+        }
+        for (i, mapping) in self.get_mapping().iter().enumerate() {
+            keys.extend(mapping.unknown_keys().into_iter().map(|key| format!("mapping.{}.{}", i, key)));
+        }
+        for (i, template) in self.get_template().iter().enumerate() {
+            keys.extend(template.unknown_keys().into_iter().map(|key| format!("template.{}.{}", i, key)));
+        }
+        keys.extend(self.get_apns().unknown_keys().into_iter().map(|key| format!("apns.{}", key)));
+        keys
     }
 
     /// translate alias to device token
     /// 
+    /// groups are expanded to their members recursively, a device reached more than once is only kept once
+    /// 
     /// if not found, return the name itself
     /// 
-    /// return {alias1: real_device_token1, not_found_alias2: not_found_alias2 ... }
-    pub fn translate_to_real_devices(&self, names: &Vec<String>) -> HashMap<String,String> {
+    /// return {alias1: real_device_token1, not_found_alias2: not_found_alias2 ... }, or an error if groups contain a cycle
+    pub fn translate_to_real_devices(&self, names: &Vec<String>) -> Result<HashMap<String,String>, String> {
         let mut devices: HashMap<String, String> = HashMap::<String, String>::new();
         let user_dict: HashMap<String, String> = self.get_user_info().iter().map( |user| (user.get_nick_name().to_string(), user.get_device_token().to_string()))
             .collect::<HashMap::<String, String>>();
-        
+        let group_dict: HashMap<String, Vec<String>> = self.get_group_info().iter().map(|group| (group.get_name().to_string(), group.get_members().clone()))
+            .collect::<HashMap::<String, Vec<String>>>();
+
+        let mut aliases: Vec<String> = Vec::<String>::new();
         for name in names {
-            devices.insert(name.clone(), user_dict.get(name).or_else(|| Some(name)).unwrap().to_string());
+            Self::expand_group(name, &group_dict, &mut Vec::new(), &mut aliases)?;
+        }
+
+        let mut seen: HashSet<String> = HashSet::<String>::new();
+        for alias in aliases {
+            let device: String = user_dict.get(&alias).unwrap_or(&alias).to_string();
+            if seen.insert(device.clone()) {
+                devices.insert(alias, device);
+            }
         }
         
-        devices
+        Ok(devices)
+    }
+
+    /// depth first expand `name` into `aliases`, `path` holds the groups currently being expanded
+    fn expand_group(name: &String, group_dict: &HashMap<String, Vec<String>>, path: &mut Vec<String>, aliases: &mut Vec<String>) -> Result<(), String> {
+        match group_dict.get(name) {
+            Some(members) => {
+                if path.contains(name) {
+                    return Err(format!("group cycle detected: {} -> {}", path.join(" -> "), name));
+                }
+                path.push(name.clone());
+                for member in members {
+                    Self::expand_group(member, group_dict, path, aliases)?;
+                }
+                path.pop();
+            },
+            None => {
+                if !aliases.contains(name) {
+                    aliases.push(name.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// lock the run file and read it again, so what other processes wrote since it was read is kept
+    ///
+    /// the lock is held until the returned file is dropped, after `save`
+    fn lock(&mut self) -> Option<File> {
+        let resolved_path: String = file_utils::resolve_runfile_path(&self.path);
+        let lock: Option<File> = match file_utils::lock_file(Path::new(&resolved_path)) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("lock {} failed, writing it unlocked: {}", resolved_path, e);
+                None
+            },
+        };
+        match file_utils::read_runfile_from_file(&resolved_path) {
+            Ok(run_file) => *self = run_file,
+            Err(e) => {
+                eprintln!("{}", e);
+                self.broken = true;
+            },
+        }
+        lock
     }
 
     fn save(&mut self) {
@@ -140,7 +507,15 @@ impl RunFile {
         if self.path != resolved_path {
             self.path = resolved_path;
         }
-        let _ = file_utils::write_runfile_to_file(&self.path, self);
+        if self.broken {
+            eprintln!("refuse to write {} over a file which does not parse, check it by `bark config check`", self.path);
+            return;
+        }
+        // only commands changing the config file get here, so a read-only one is an error for them alone
+        if let Err(e) = file_utils::write_runfile_to_file(&self.path, self) {
+            eprintln!("write {} failed: {}", self.path, e);
+            exit(config::EXIT_INVALID_INPUT);
+        }
     }
 }
 
@@ -155,6 +530,7 @@ mod tests {
     impl RunFile {
         pub fn new_for_test() -> RunFile {
             RunFile {
+                schema_version: SCHEMA_VERSION,
                 user_info: Some(
                     vec!(
                         UserInfo::new(
@@ -167,31 +543,184 @@ mod tests {
                         )
                     )
                 ),
-               token: Some(Token::new_for_test(0, "token")),
-               path: "test_run_file.toml".to_string()
+               group: None,
+               mapping: None,
+               template: None,
+               profile: None,
+               apns: None,
+               unknown: Table::new(),
+               path: "test_run_file.toml".to_string(),
+               broken: false,
             }
         }
     }
 
 
+    #[test]
+    fn test_problems() {
+        let token: String = "a".repeat(64);
+        let mut run_file = RunFile::new_for_test();
+        run_file.user_info = Some(vec![
+            UserInfo::new("alice", &token),
+            UserInfo::new("alice", &token),
+            UserInfo::new("bob", "not a token"),
+        ]);
+        run_file.group = Some(vec![
+            GroupInfo::new("bob", vec!["alice".to_string()]),
+            GroupInfo::new("team", vec!["alice".to_string(), "carol".to_string(), token.clone()]),
+        ]);
+        assert_eq!(
+            vec![
+                "user alice is defined 2 times".to_string(),
+                "device token of user bob is not 64 hex characters".to_string(),
+                "bob is both a user and a group".to_string(),
+                "member carol of group team is neither a user, a group nor a device token".to_string(),
+            ],
+            run_file.problems()
+        );
+
+        assert_eq!(
+            vec!["user alice, the first one with the name is kept".to_string(), "group bob, it is named like a user".to_string()],
+            run_file.dedupe()
+        );
+        assert_eq!(vec!["device token of user bob is not 64 hex characters".to_string(), "member carol of group team is neither a user, a group nor a device token".to_string()], run_file.problems());
+    }
+
+    #[test]
+    fn test_mapped_receivers() {
+        let mut run_file = RunFile::new_for_test();
+        run_file.mapping = Some(vec![Mapping::new("owner/repo", vec!["nick_name2".to_string(), "oncall".to_string()])]);
+
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+        assert_eq!(names(&["nick_name2", "oncall", "nick_name1"]), run_file.mapped_receivers(&names(&["owner/repo", "nick_name1", "nick_name2", "octocat"])));
+        assert!(run_file.mapped_receivers(&names(&["octocat"])).is_empty());
+    }
+
     #[test]
     fn test_run_file() {
         let run_file = RunFile::new_for_test();
 
         let r = run_file.to_string().unwrap();
-        assert_eq!("[[user_info]]\nnick_name = \"nick_name1\"\ndevice_token = \"device_token1\"\n\n[[user_info]]\nnick_name = \"nick_name2\"\ndevice_token = \"device_token2\"\n\n[token]\nrefresh_at = 0\ntoken = \"token\"\n", r);
+        assert_eq!("schema_version = 2\n\n[[user_info]]\nnick_name = \"nick_name1\"\ndevice_token = \"device_token1\"\n\n[[user_info]]\nnick_name = \"nick_name2\"\ndevice_token = \"device_token2\"\n", r);
         
         let de_run_file = RunFile::from_string(&r).unwrap();
         let rr = de_run_file.to_string().unwrap();
         assert_eq!(r, rr);
     }
 
+    const V0_BASELINE: &str = include_str!("../../tests/fixtures/run_file_v0_baseline.toml");
+    const V0: &str = include_str!("../../tests/fixtures/run_file_v0.toml");
+    const V1: &str = include_str!("../../tests/fixtures/run_file_v1.toml");
+    const V2: &str = include_str!("../../tests/fixtures/run_file_v2.toml");
+
+    #[test]
+    fn test_migrate_v0() {
+        // the layout of the first release, users and the token only
+        let run_file = RunFile::from_string(V0_BASELINE).unwrap();
+        assert_eq!(SCHEMA_VERSION, run_file.schema_version);
+        assert_eq!(vec!["alice", "bob"], run_file.get_user_info().iter().map(|u| u.get_nick_name()).collect::<Vec<&str>>());
+        assert_eq!(format!("schema_version = 2\n\n{}", V0_BASELINE.split("\n[token]").next().unwrap()), run_file.to_string().unwrap());
+
+        // every section added before the version was
+        let run_file = RunFile::from_string(V0).unwrap();
+        assert_eq!(SCHEMA_VERSION, run_file.schema_version);
+        assert!(run_file.get_user_info_by_name("bob").unwrap().get_encryption().is_some());
+        assert_eq!(&vec!["alice".to_string(), "bob".to_string()], run_file.get_group_info_by_name("team").unwrap().get_members());
+        assert_eq!(1, run_file.get_mapping().len());
+        assert_eq!(1, run_file.get_template().len());
+        assert!(run_file.get_profile("team").is_some());
+        assert_eq!(crate::module::apns_config::ApnsEnvironment::Sandbox, run_file.get_apns().environment());
+        assert!(run_file.unknown_keys().is_empty());
+        let content: String = run_file.to_string().unwrap();
+        assert!(!content.contains("[token]") && !content.contains("[[credential_token]]"));
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        // the cached tokens are dropped, the rest is kept as it is
+        let run_file = RunFile::from_string(V1).unwrap();
+        assert_eq!(SCHEMA_VERSION, run_file.schema_version);
+        assert_eq!(V2, run_file.to_string().unwrap());
+    }
+
+    #[test]
+    fn test_keep_unknown() {
+        let run_file = RunFile::from_string(V2).unwrap();
+        assert_eq!(V2, run_file.to_string().unwrap());
+
+        // every section keeps what this bark does not know, also when it changes
+        let sections: [(&str, &str); 6] = [
+            ("reminder", "[reminder]\nevery = \"1d\""),
+            ("user_info.0.ring", "ring = \"bell.caf\"\n\n[[user_info]]"),
+            ("group.0.color", "]\ncolor = \"red\"\n\n[[mapping]]"),
+            ("mapping.0.events", "events = [\"push\"]\n\n[[template]]"),
+            ("template.0.subtitle", "subtitle = \"{{env}}\"\n\n[profile.team]"),
+            ("apns.expiration", "environment = \"sandbox\"\nexpiration = 3600\n"),
+        ];
+        assert_eq!(sections.iter().map(|(key, _)| key.to_string()).collect::<Vec<String>>(), run_file.unknown_keys());
+        let mut run_file = RunFile::from_string(V2).unwrap();
+        run_file.user_info.as_mut().unwrap().push(UserInfo::new("carol", &"c".repeat(64)));
+        let content: String = run_file.to_string().unwrap();
+        for (key, kept) in sections {
+            assert!(content.contains(kept), "{} not kept in\n{}", key, content);
+        }
+
+        // a newer file keeps its version and what this bark does not know
+        let newer: String = V2.replacen("schema_version = 2", "schema_version = 3", 1);
+        let run_file = RunFile::from_string(&newer).unwrap();
+        assert_eq!(newer, run_file.to_string().unwrap());
+        assert!(run_file.problems().contains(&"schema_version 3 is newer than 2 this bark knows, what it does not know is kept as it is".to_string()));
+
+        assert!(RunFile::from_string("schema_version = \"2\"\n").is_err());
+    }
+
+    #[test]
+    fn test_user_by_device_token() {
+        let run_file = RunFile::from_string(V2).unwrap();
+        let bob = run_file.get_user_info_by_device_token(&"b".repeat(64)).unwrap();
+        assert_eq!("bob", bob.get_nick_name());
+        assert!(bob.get_encryption().is_some());
+        assert!(run_file.get_user_info_by_device_token("bob").is_none());
+    }
+
     #[test]
     fn test_find_device_by_name() {
         
         let run_file = RunFile::new_for_test();
         
-        assert_eq!(HashMap::from([("nick_name1".to_string(), "device_token1".to_string())]), run_file.translate_to_real_devices(vec!["nick_name1".to_string()].as_ref()));
-        assert_eq!(HashMap::from([("nick_name3".to_string(), "nick_name3".to_string())]), run_file.translate_to_real_devices(vec!["nick_name3".to_string()].as_ref()));
+        assert_eq!(HashMap::from([("nick_name1".to_string(), "device_token1".to_string())]), run_file.translate_to_real_devices(vec!["nick_name1".to_string()].as_ref()).unwrap());
+        assert_eq!(HashMap::from([("nick_name3".to_string(), "nick_name3".to_string())]), run_file.translate_to_real_devices(vec!["nick_name3".to_string()].as_ref()).unwrap());
+    }
+
+    #[test]
+    fn test_find_device_by_group() {
+        let mut run_file = RunFile::new_for_test();
+        run_file.group = Some(vec!(
+            GroupInfo::new("oncall", vec!["nick_name1".to_string(), "team".to_string()]),
+            GroupInfo::new("team", vec!["nick_name2".to_string(), "nick_name1".to_string(), "device_token2".to_string()])
+        ));
+
+        let r = run_file.to_string().unwrap();
+        assert!(r.contains("[[group]]\nname = \"oncall\"\nmembers = [\n    \"nick_name1\",\n    \"team\",\n]\n"));
+
+        // nested group expanded, nick_name1 and device_token2 only kept once
+        assert_eq!(
+            HashMap::from([("nick_name1".to_string(), "device_token1".to_string()), ("nick_name2".to_string(), "device_token2".to_string())]),
+            run_file.translate_to_real_devices(vec!["oncall".to_string(), "team".to_string(), "nick_name1".to_string()].as_ref()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_group_cycle() {
+        let mut run_file = RunFile::new_for_test();
+        run_file.group = Some(vec!(
+            GroupInfo::new("a", vec!["nick_name1".to_string(), "b".to_string()]),
+            GroupInfo::new("b", vec!["a".to_string()])
+        ));
+
+        assert_eq!(
+            Err("group cycle detected: a -> b -> a".to_string()),
+            run_file.translate_to_real_devices(vec!["a".to_string()].as_ref())
+        );
     }
 }
\ No newline at end of file
@@ -0,0 +1,144 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// accepted by APNs
+    Delivered,
+    /// rejected by APNs or not reachable
+    Failed,
+    /// not an alias and not a valid device token, never sent
+    Invalid,
+}
+
+/// outcome of sending to one receiver
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SendResult {
+    alias: String,
+    device_token: String,
+    status: Status,
+    http_status: Option<u16>,
+    reason: Option<String>,
+    apns_id: Option<String>,
+    /// how many times the msg was sent to this receiver
+    attempts: u32,
+}
+
+impl SendResult {
+    pub fn new(alias: &str, device_token: &str, status: Status, http_status: Option<u16>, reason: Option<String>, apns_id: Option<String>, attempts: u32) -> SendResult {
+        SendResult {
+            alias: alias.to_string(),
+            device_token: Self::mask_device_token(device_token),
+            status,
+            http_status,
+            reason,
+            apns_id,
+            attempts,
+        }
+    }
+
+    /// keep only head and tail of a device token, it is enough to tell devices apart
+    pub fn mask_device_token(device_token: &str) -> String {
+        if device_token.len() <= 10 || !device_token.is_ascii() {
+            return "*".repeat(device_token.chars().count());
+        }
+        format!("{}…{}", &device_token[..6], &device_token[device_token.len() - 4..])
+    }
+
+    /// print failed receivers to stderr
+    pub fn print_text(results: &[SendResult]) {
+        results.iter().filter_map(|r| r.failure()).for_each(|f| eprintln!("{}", f));
+    }
+
+    /// why sending to this receiver failed, None if delivered
+    pub fn failure(&self) -> Option<String> {
+        if self.status == Status::Delivered {
+            return None;
+        }
+        let attempts: String = if self.attempts > 1 { format!(" after {} attempts", self.attempts) } else { String::new() };
+        match &self.reason {
+            Some(reason) => Some(format!("Send to {} failed{}: {}", self.alias, attempts, reason)),
+            None => Some(format!("Send to {} failed{}", self.alias, attempts)),
+        }
+    }
+
+    pub fn print_json(results: &[SendResult]) {
+        match serde_json::to_string(results) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("serialize send result failed: {}", e),
+        }
+    }
+
+    /// process exit code summarizing all results
+    pub fn exit_code(results: &[SendResult]) -> i32 {
+        let delivered: usize = results.iter().filter(|r| r.status == Status::Delivered).count();
+        if results.is_empty() || results.iter().all(|r| r.status == Status::Invalid) {
+            config::EXIT_INVALID_INPUT
+        } else if delivered == results.len() {
+            config::EXIT_ALL_DELIVERED
+        } else if delivered == 0 {
+            config::EXIT_ALL_FAILED
+        } else {
+            config::EXIT_PARTIAL_FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(status: Status) -> SendResult {
+        SendResult::new("alias", &"a".repeat(64), status, None, None, None, 1)
+    }
+
+    #[test]
+    fn test_mask_device_token() {
+        assert_eq!("0123ab…wxyz", SendResult::mask_device_token("0123abcdefghijklmnopqrstuvwxyz"));
+        assert_eq!("*****", SendResult::mask_device_token("short"));
+    }
+
+    #[test]
+    fn test_send_result_json() {
+        let r = SendResult::new("alice", "0123abcdefghijklmnopqrstuvwxyz", Status::Failed, Some(400), Some("BadDeviceToken".to_string()), Some("apns-id".to_string()), 1);
+        assert_eq!(
+            "{\"alias\":\"alice\",\"device_token\":\"0123ab…wxyz\",\"status\":\"failed\",\"http_status\":400,\"reason\":\"BadDeviceToken\",\"apns_id\":\"apns-id\",\"attempts\":1}",
+            serde_json::to_string(&r).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(config::EXIT_ALL_DELIVERED, SendResult::exit_code(&[result(Status::Delivered), result(Status::Delivered)]));
+        assert_eq!(config::EXIT_PARTIAL_FAILURE, SendResult::exit_code(&[result(Status::Delivered), result(Status::Failed)]));
+        assert_eq!(config::EXIT_PARTIAL_FAILURE, SendResult::exit_code(&[result(Status::Delivered), result(Status::Invalid)]));
+        assert_eq!(config::EXIT_ALL_FAILED, SendResult::exit_code(&[result(Status::Failed), result(Status::Invalid)]));
+        assert_eq!(config::EXIT_INVALID_INPUT, SendResult::exit_code(&[result(Status::Invalid)]));
+        assert_eq!(config::EXIT_INVALID_INPUT, SendResult::exit_code(&[]));
+    }
+}
@@ -0,0 +1,178 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use bark_dev::msg;
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+use crate::util::template_utils;
+
+/// a named msg with `{{var}}` placeholders, fields not set are left to the command line
+#[derive(clap::Args, Serialize, Deserialize, Clone, Debug)]
+pub struct Template {
+    /// name of the template, like "deploy"
+    name: String,
+    /// title
+    #[arg(short, long, required = false)]
+    title: Option<String>,
+    /// msg content
+    #[arg(short = 'm', long, required = false)]
+    body: Option<String>,
+    /// Push Interruption Level(active, timeSensitive, passive)
+    #[arg(short, long, required = false)]
+    level: Option<String>,
+    /// ringtone of the push
+    #[arg(short, long, required = false)]
+    sound: Option<String>,
+    /// Group messages
+    #[arg(short, long, required = false)]
+    group: Option<String>,
+    /// icon of the push
+    #[arg(short, long, required = false)]
+    icon: Option<String>,
+    /// The URL to jump to when clicking the push
+    #[arg(long, required = false)]
+    url: Option<String>,
+    /// settings of the template this bark does not know, written back as they are
+    #[arg(skip)]
+    #[serde(flatten)]
+    unknown: Table,
+}
+
+const HEAD_NAME: &str = "TEMPLATE";
+const HEAD_TITLE: &str = "TITLE";
+
+impl Template {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+    pub fn get_body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+    pub fn get_level(&self) -> Option<&str> {
+        self.level.as_deref()
+    }
+    pub fn get_sound(&self) -> Option<&str> {
+        self.sound.as_deref()
+    }
+    pub fn get_group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+    pub fn get_icon(&self) -> Option<&str> {
+        self.icon.as_deref()
+    }
+    pub fn get_url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+    pub fn unknown_keys(&self) -> Vec<String> {
+        self.unknown.keys().cloned().collect()
+    }
+
+    fn fields(&self) -> [(&'static str, Option<&str>); 7] {
+        [
+            ("title", self.get_title()),
+            ("body", self.get_body()),
+            ("level", self.get_level()),
+            ("sound", self.get_sound()),
+            ("group", self.get_group()),
+            ("icon", self.get_icon()),
+            ("url", self.get_url()),
+        ]
+    }
+
+    /// a template which can be rendered: a known level and closed placeholders
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("template name can not be empty".to_string());
+        }
+        if let Some(level) = &self.level {
+            // a placeholder is only known when the template is rendered
+            if !level.contains("{{") && msg::Level::from_str(level).is_none() {
+                return Err(format!("invalid level: {}", level));
+            }
+        }
+        for (_, value) in self.fields() {
+            if let Some(value) = value {
+                template_utils::render(value, |_| Some(String::new()))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pretty_print(templates: Vec<Template>) {
+        let max_name_len: usize = std::cmp::max(templates.iter().map(|t| t.name.len()).max().unwrap_or(1), HEAD_NAME.len());
+
+        println!("{:<max_name_len$}    {}", HEAD_NAME, HEAD_TITLE);
+        println!("{:-<max_name_len$}    {:-<2$}", "", "", HEAD_TITLE.len());
+
+        templates.iter().for_each(|t| {
+            println!("{:<max_name_len$}    {}", t.name, t.title.as_deref().unwrap_or_default());
+        });
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "name: {}", self.name)?;
+        for (field, value) in self.fields() {
+            if let Some(value) = value {
+                writeln!(f, "{}: {}", field, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(clap::Parser)]
+    struct Add {
+        #[command(flatten)]
+        template: Template,
+    }
+
+    fn template(args: &[&str]) -> Template {
+        Add::try_parse_from([&["add"], args].concat()).unwrap().template
+    }
+
+    #[test]
+    fn test_template() {
+        let t = template(&["deploy", "-t", "deploy {{env}}", "-m", "{{version}} is live", "-l", "timeSensitive"]);
+        assert_eq!("deploy", t.get_name());
+        assert_eq!(Some("deploy {{env}}"), t.get_title());
+        assert!(t.validate().is_ok());
+        assert_eq!("name: deploy\ntitle: deploy {{env}}\nbody: {{version}} is live\nlevel: timeSensitive\n", t.to_string());
+
+        assert!(template(&["deploy", "-l", "loud"]).validate().is_err());
+        assert!(template(&["deploy", "-l", "{{level}}"]).validate().is_ok());
+        assert!(template(&["deploy", "-m", "{{version"]).validate().is_err());
+
+        Template::pretty_print(vec![t]);
+    }
+}
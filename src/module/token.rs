@@ -23,18 +23,26 @@
 use serde::{Serialize, Deserialize};
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Token {
+    /// id of the own credential which signed the token, None for the built-in one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credential: Option<String>,
     refresh_at: u64,
     token: String
 }
 
 impl Token {
-    pub fn new(refresh_at: u64, token: &str) -> Token {
+    pub fn new(credential: Option<&str>, refresh_at: u64, token: &str) -> Token {
         Token {
-            refresh_at: refresh_at,
+            credential: credential.map(str::to_string),
+            refresh_at,
             token: token.to_string()
         }
     }
 
+    pub fn get_credential(&self) -> Option<&str> {
+        self.credential.as_deref()
+    }
+
     pub fn get_token(&self) -> &str {
        &self.token
     }
@@ -49,23 +57,18 @@ impl Token {
 mod tests {
     use super::*;
     
-    impl Token {
-        pub fn new_for_test(refresh_at: u64,token: &str) -> Token {
-            Token {
-                refresh_at: refresh_at,
-                token: token.to_string()
-           }
-        }
-    }
-
     #[test]
     fn test_token() {
         let token = Token {
+            credential: None,
             refresh_at: 0,
             token: "token".to_string()
         };
 
         let r = toml::to_string_pretty(&token);
         assert_eq!("refresh_at = 0\ntoken = \"token\"\n", r.unwrap());
+
+        let token = Token::new(Some("TEAM.KEY"), 0, "token");
+        assert_eq!("credential = \"TEAM.KEY\"\nrefresh_at = 0\ntoken = \"token\"\n", toml::to_string_pretty(&token).unwrap());
     }
 }
\ No newline at end of file
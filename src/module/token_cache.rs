@@ -0,0 +1,140 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{fs::File, path::Path};
+
+use serde::{Serialize, Deserialize};
+
+use crate::util::file_utils;
+
+use super::token::Token;
+
+/// tokens signed for APNs, kept in a cache file apart from the config file,
+/// so sending never writes the config file and it can be read-only
+#[derive(Serialize, Deserialize, Default)]
+pub struct TokenCache {
+    /// token of the built-in credential
+    token: Option<Token>,
+    /// tokens of own credentials, one per credential
+    credential_token: Option<Vec<Token>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    path: String,
+}
+
+impl TokenCache {
+    pub fn new_empty(path: String) -> Self {
+        Self {
+            path,
+            ..Default::default()
+        }
+    }
+
+    pub fn new(cache: TokenCache, path: String) -> Self {
+        Self {
+            path,
+            ..cache
+        }
+    }
+
+    pub fn get_token(&self) -> Option<Token> {
+        self.token.clone()
+    }
+
+    pub fn set_token(&mut self, time_stamp: u64, token: &str) {
+        let _lock: Option<File> = self.lock();
+        if self.token.as_ref().is_some_and(|t| t.get_refresh_at() == time_stamp) {
+            return;
+        }
+        self.token = Some(Token::new(None, time_stamp, token));
+        self.save();
+    }
+
+    /// the cached token of the own credential `id`
+    pub fn get_credential_token(&self, id: &str) -> Option<Token> {
+        self.credential_token.as_ref()?.iter().find(|t| t.get_credential() == Some(id)).cloned()
+    }
+
+    pub fn set_credential_token(&mut self, id: &str, time_stamp: u64, token: &str) {
+        let _lock: Option<File> = self.lock();
+        let mut tokens: Vec<Token> = self.credential_token.take().unwrap_or_default();
+        if tokens.iter().any(|t| t.get_credential() == Some(id) && t.get_refresh_at() == time_stamp) {
+            self.credential_token = Some(tokens);
+            return;
+        }
+        tokens.retain(|t| t.get_credential() != Some(id));
+        tokens.push(Token::new(Some(id), time_stamp, token));
+        self.credential_token = Some(tokens);
+        self.save();
+    }
+
+    pub fn to_string(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(&self)
+    }
+
+    pub fn from_string(str: &str) -> Result<TokenCache, toml::de::Error> {
+        toml::from_str(str)
+    }
+
+    /// lock the cache file and read it again, so a token cached by another process meanwhile is kept
+    ///
+    /// the cache is only a cache, when it can not be locked it is written unlocked
+    fn lock(&mut self) -> Option<File> {
+        let lock: Option<File> = file_utils::lock_file(Path::new(&self.path)).ok();
+        *self = file_utils::read_token_cache_from_file(&self.path);
+        lock
+    }
+
+    /// a cache which can not be written is signed again next time, so the error is dropped
+    fn save(&self) {
+        let _ = file_utils::write_token_cache_to_file(&self.path, self);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_cache() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("bark_token_cache_test").join("token.toml");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+        let path: String = path.to_string_lossy().to_string();
+
+        let mut cache: TokenCache = file_utils::read_token_cache_from_file(&path);
+        assert!(cache.get_token().is_none());
+        cache.set_token(1760000000, "built-in");
+        cache.set_credential_token("TEAM.KEY", 1760000000, "own");
+        cache.set_credential_token("TEAM.KEY", 1760002700, "own again");
+
+        let cache: TokenCache = file_utils::read_token_cache_from_file(&path);
+        assert_eq!("built-in", cache.get_token().unwrap().get_token());
+        assert_eq!("own again", cache.get_credential_token("TEAM.KEY").unwrap().get_token());
+        assert!(cache.get_credential_token("TEAM.OTHER").is_none());
+
+        // a broken cache is an empty one
+        std::fs::write(&path, "token = ").unwrap();
+        assert!(file_utils::read_token_cache_from_file(&path).get_token().is_none());
+
+        std::fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+}
@@ -22,21 +22,33 @@
 
 
 use serde::{Serialize, Deserialize};
+use toml::Table;
+
+use super::encryption::Encryption;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserInfo {
     nick_name: String,
-    device_token: String
+    device_token: String,
+    /// msgs to the user are encrypted with it, whatever is passed when sending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    /// settings of the user this bark does not know, written back as they are
+    #[serde(flatten)]
+    unknown: Table,
 }
 
 const HEAD_NICKE_NAME: &str = "ALIAS";
 const HEAD_DEVICE_TOKEN: &str = "DEVICE_TOKEN";
+const HEAD_ENCRYPTION: &str = "ENCRYPTION";
 
 impl UserInfo {
     pub fn new(nick_name: &str, device_token: &str) -> UserInfo {
         UserInfo {
             nick_name: nick_name.to_string(),
-            device_token: device_token.to_string()
+            device_token: device_token.to_string(),
+            encryption: None,
+            unknown: Table::new(),
         }
    }
    
@@ -46,16 +58,26 @@ impl UserInfo {
     pub fn get_device_token(&self) -> &str {
        &self.device_token
     }
+    pub fn get_encryption(&self) -> Option<&Encryption> {
+        self.encryption.as_ref()
+    }
+    pub fn set_encryption(&mut self, encryption: Option<Encryption>) {
+        self.encryption = encryption;
+    }
+    pub fn unknown_keys(&self) -> Vec<String> {
+        self.unknown.keys().cloned().collect()
+    }
 
     pub fn pretty_print(users: Vec<UserInfo>) {
-        let max_nicke_name_len: usize = std::cmp::max(users.iter().map(|u| u.nick_name.len()).max().unwrap_or_else(|| 1), HEAD_NICKE_NAME.len());
-        let max_device_token_len: usize = std::cmp::max(users.iter().map(|u| u.device_token.len()).max().unwrap_or_else(|| 1), HEAD_DEVICE_TOKEN.len());
+        let max_nicke_name_len: usize = std::cmp::max(users.iter().map(|u| u.nick_name.len()).max().unwrap_or(1), HEAD_NICKE_NAME.len());
+        let max_device_token_len: usize = std::cmp::max(users.iter().map(|u| u.device_token.len()).max().unwrap_or(1), HEAD_DEVICE_TOKEN.len());
     
-        println!("{:<max_nicke_name_len$}    {:<max_device_token_len$}", HEAD_NICKE_NAME, HEAD_DEVICE_TOKEN);
-        println!("{:-<max_nicke_name_len$}    {:-<max_device_token_len$}", "", "");
+        println!("{:<max_nicke_name_len$}    {:<max_device_token_len$}    {}", HEAD_NICKE_NAME, HEAD_DEVICE_TOKEN, HEAD_ENCRYPTION);
+        println!("{:-<max_nicke_name_len$}    {:-<max_device_token_len$}    {:-<3$}", "", "", "", HEAD_ENCRYPTION.len());
 
         users.iter().for_each(|u| {
-            println!("{:<max_nicke_name_len$}    {:<max_device_token_len$}", u.nick_name, u.device_token);
+            let encryption: String = u.encryption.as_ref().map(|e| e.to_string()).unwrap_or_default();
+            println!("{:<max_nicke_name_len$}    {:<max_device_token_len$}    {}", u.nick_name, u.device_token, encryption);
         });
     }
 
@@ -65,7 +87,7 @@ impl std::str::FromStr for UserInfo {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (nick_name, device_token) = s.split_once(":").unwrap_or_else(|| ("",""));
+        let (nick_name, device_token) = s.split_once(":").unwrap_or(("",""));
         if nick_name.is_empty() || device_token.is_empty() {
             return Err("Please input valid char like \"alias:device_token\"".to_string());
         }
@@ -87,7 +109,7 @@ mod tests {
     #[test]
     fn test_user_info_from_str() {
         let mut us: Vec<UserInfo> = Vec::new();
-        for i in vec![1,9,10,99,100,999,1000] {
+        for i in [1,9,10,99,100,999,1000] {
             let u = format!("alias_{}:xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx{}", i, i);
             let u = u.parse::<UserInfo>().unwrap();
             us.push(u);
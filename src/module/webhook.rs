@@ -0,0 +1,258 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+use serde_json::Value;
+
+use crate::cmd::CMD;
+
+/// workflow run conclusions worth a push
+const FAILED_CONCLUSIONS: [&str; 3] = ["failure", "timed_out", "startup_failure"];
+
+/// a webhook event worth a push
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    title: String,
+    body: String,
+    url: String,
+    /// repository the event is about, used as group
+    repository: String,
+    /// repository or user names to look up receivers for, in order
+    names: Vec<String>,
+}
+
+impl Event {
+    pub fn get_names(&self) -> &Vec<String> {
+        &self.names
+    }
+
+    /// a GitHub event by its `X-GitHub-Event` header, None for events not worth a push
+    pub fn from_github(event: &str, body: &[u8]) -> Result<Option<Event>, String> {
+        let json: Value = Self::parse(body)?;
+        let repository: String = text(&json, "/repository/full_name");
+        match (event, text(&json, "/action").as_str()) {
+            ("workflow_run", "completed") => {
+                let conclusion: String = text(&json, "/workflow_run/conclusion");
+                if !FAILED_CONCLUSIONS.contains(&conclusion.as_str()) {
+                    return Ok(None);
+                }
+                let actor: String = text(&json, "/workflow_run/triggering_actor/login");
+                Ok(Some(Event {
+                    title: format!("CI {}: {}", conclusion.replace('_', " "), repository),
+                    body: format!("{} on {}: {}\nby {}",
+                        text(&json, "/workflow_run/name"),
+                        text(&json, "/workflow_run/head_branch"),
+                        text(&json, "/workflow_run/display_title"),
+                        actor),
+                    url: text(&json, "/workflow_run/html_url"),
+                    names: vec![repository.clone(), actor],
+                    repository,
+                }))
+            },
+            ("pull_request", "review_requested") => {
+                // a review is requested from a user or from a team
+                let reviewer: String = match text(&json, "/requested_reviewer/login") {
+                    login if login.is_empty() => text(&json, "/requested_team/slug"),
+                    login => login,
+                };
+                Ok(Some(Event {
+                    title: format!("Review requested: {}#{}", repository, text(&json, "/pull_request/number")),
+                    body: format!("{}\nfrom {}", text(&json, "/pull_request/title"), text(&json, "/sender/login")),
+                    url: text(&json, "/pull_request/html_url"),
+                    names: vec![reviewer],
+                    repository,
+                }))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// a GitLab event by its `X-Gitlab-Event` header, None for events not worth a push
+    pub fn from_gitlab(event: &str, body: &[u8]) -> Result<Option<Event>, String> {
+        let json: Value = Self::parse(body)?;
+        let repository: String = text(&json, "/project/path_with_namespace");
+        match event {
+            "Pipeline Hook" => {
+                if text(&json, "/object_attributes/status") != "failed" {
+                    return Ok(None);
+                }
+                let user: String = text(&json, "/user/username");
+                let url: String = match text(&json, "/object_attributes/url") {
+                    url if url.is_empty() => format!("{}/-/pipelines/{}", text(&json, "/project/web_url"), text(&json, "/object_attributes/id")),
+                    url => url,
+                };
+                Ok(Some(Event {
+                    title: format!("Pipeline failed: {}", repository),
+                    body: format!("{}: {}\nby {}", text(&json, "/object_attributes/ref"), text(&json, "/commit/title"), user),
+                    url,
+                    names: vec![repository.clone(), user],
+                    repository,
+                }))
+            },
+            "Merge Request Hook" => {
+                let usernames = |pointer: &str| -> Vec<String> {
+                    json.pointer(pointer).and_then(|v| v.as_array()).map_or(Vec::new(), |users| {
+                        users.iter().map(|u| text(u, "/username")).filter(|u| !u.is_empty()).collect()
+                    })
+                };
+                // reviewers added by this event, all of them when the merge request is opened
+                let reviewers: Vec<String> = if json.pointer("/changes/reviewers").is_some() {
+                    let previous: Vec<String> = usernames("/changes/reviewers/previous");
+                    usernames("/changes/reviewers/current").into_iter().filter(|r| !previous.contains(r)).collect()
+                } else if text(&json, "/object_attributes/action") == "open" {
+                    usernames("/reviewers")
+                } else {
+                    Vec::new()
+                };
+                if reviewers.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(Event {
+                    title: format!("Review requested: {}!{}", repository, text(&json, "/object_attributes/iid")),
+                    body: format!("{}\nfrom {}", text(&json, "/object_attributes/title"), text(&json, "/user/username")),
+                    url: text(&json, "/object_attributes/url"),
+                    names: reviewers,
+                    repository,
+                }))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn parse(body: &[u8]) -> Result<Value, String> {
+        serde_json::from_slice(body).map_err(|e| format!("invalid webhook: {}", e))
+    }
+
+    /// the options to send this event with, options not set by the event keep the ones of `base`
+    pub fn to_cmd(&self, base: &CMD, receivers: Vec<String>) -> CMD {
+        let mut cmd: CMD = base.clone();
+        cmd.receiver = receivers;
+        cmd.title = self.title.clone();
+        cmd.msg = Some(self.body.clone());
+        if !self.url.is_empty() {
+            cmd.url = Some(self.url.clone());
+        }
+        if !self.repository.is_empty() {
+            cmd.group = cmd.group.or(Some(self.repository.clone()));
+        }
+        cmd
+    }
+}
+
+/// check `X-Hub-Signature-256` like "sha256=<hex>", the HMAC-SHA256 of the body keyed by the secret
+pub fn verify_github_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(signature) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let expected: Vec<u8> = match PKey::hmac(secret.as_bytes())
+        .and_then(|key| Signer::new(MessageDigest::sha256(), &key).and_then(|mut s| s.sign_oneshot_to_vec(body)))
+    {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    let expected: String = expected.iter().map(|b| format!("{:02x}", b)).collect();
+    let signature: String = signature.to_ascii_lowercase();
+    signature.len() == expected.len() && memcmp::eq(signature.as_bytes(), expected.as_bytes())
+}
+
+/// check `X-Gitlab-Token`, GitLab sends the secret token itself
+pub fn verify_gitlab_token(secret: &str, token: &str) -> bool {
+    secret.len() == token.len() && memcmp::eq(secret.as_bytes(), token.as_bytes())
+}
+
+/// the text at `pointer`, numbers as written, empty if missing
+fn text(json: &Value, pointer: &str) -> String {
+    match json.pointer(pointer) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_github_signature() {
+        // example from the GitHub docs about validating webhook deliveries
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        assert!(verify_github_signature("It's a Secret to Everybody", b"Hello, World!", signature));
+        assert!(!verify_github_signature("It's a Secret to Everybody", b"Hello, World?", signature));
+        assert!(!verify_github_signature("another secret", b"Hello, World!", signature));
+        assert!(!verify_github_signature("It's a Secret to Everybody", b"Hello, World!", &signature[7..]));
+        assert!(!verify_github_signature("It's a Secret to Everybody", b"Hello, World!", "sha256=00"));
+
+        assert!(verify_gitlab_token("token", "token"));
+        assert!(!verify_gitlab_token("token", "token2"));
+    }
+
+    #[test]
+    fn test_github_events() {
+        let failed = br#"{"action":"completed","repository":{"full_name":"66f94eae/bark"},"workflow_run":{"name":"CI","head_branch":"main","display_title":"Fix build","conclusion":"failure","html_url":"https://github.com/66f94eae/bark/actions/runs/1","triggering_actor":{"login":"octocat"}}}"#;
+        let event = Event::from_github("workflow_run", failed).unwrap().unwrap();
+        assert_eq!("CI failure: 66f94eae/bark", event.title);
+        assert_eq!("CI on main: Fix build\nby octocat", event.body);
+        assert_eq!(&vec!["66f94eae/bark".to_string(), "octocat".to_string()], event.get_names());
+
+        let succeeded = String::from_utf8_lossy(failed).replace("failure", "success");
+        assert_eq!(None, Event::from_github("workflow_run", succeeded.as_bytes()).unwrap());
+
+        let review = br#"{"action":"review_requested","number":7,"repository":{"full_name":"66f94eae/bark"},"pull_request":{"number":7,"title":"Add serve","html_url":"https://github.com/66f94eae/bark/pull/7"},"requested_reviewer":{"login":"alice"},"sender":{"login":"bob"}}"#;
+        let event = Event::from_github("pull_request", review).unwrap().unwrap();
+        assert_eq!("Review requested: 66f94eae/bark#7", event.title);
+        assert_eq!(&vec!["alice".to_string()], event.get_names());
+
+        let base = CMD::try_parse_from(["bark", "-r", "nobody", "-m", "-"]).unwrap();
+        let cmd = event.to_cmd(&base, vec!["alice".to_string()]);
+        assert_eq!(Some("https://github.com/66f94eae/bark/pull/7".to_string()), cmd.url);
+        assert_eq!(Some("66f94eae/bark".to_string()), cmd.group);
+        assert_eq!(Some("Add serve\nfrom bob".to_string()), cmd.msg);
+
+        assert_eq!(None, Event::from_github("push", b"{}").unwrap());
+        assert!(Event::from_github("push", b"not json").is_err());
+    }
+
+    #[test]
+    fn test_gitlab_events() {
+        let pipeline = br#"{"object_kind":"pipeline","object_attributes":{"id":31,"ref":"main","status":"failed","url":"https://gitlab.com/group/project/-/pipelines/31"},"user":{"username":"alice"},"project":{"path_with_namespace":"group/project","web_url":"https://gitlab.com/group/project"},"commit":{"title":"Bump deps"}}"#;
+        let event = Event::from_gitlab("Pipeline Hook", pipeline).unwrap().unwrap();
+        assert_eq!("Pipeline failed: group/project", event.title);
+        assert_eq!("main: Bump deps\nby alice", event.body);
+        assert_eq!("https://gitlab.com/group/project/-/pipelines/31", event.url);
+
+        let running = String::from_utf8_lossy(pipeline).replace("failed", "running");
+        assert_eq!(None, Event::from_gitlab("Pipeline Hook", running.as_bytes()).unwrap());
+
+        let update = br#"{"object_kind":"merge_request","user":{"username":"bob"},"project":{"path_with_namespace":"group/project"},"object_attributes":{"iid":5,"title":"Add serve","action":"update","url":"https://gitlab.com/group/project/-/merge_requests/5"},"changes":{"reviewers":{"previous":[{"username":"carol"}],"current":[{"username":"carol"},{"username":"alice"}]}}}"#;
+        let event = Event::from_gitlab("Merge Request Hook", update).unwrap().unwrap();
+        assert_eq!("Review requested: group/project!5", event.title);
+        assert_eq!(&vec!["alice".to_string()], event.get_names());
+
+        let open = br#"{"object_attributes":{"iid":5,"action":"open"},"reviewers":[{"username":"carol"}]}"#;
+        assert_eq!(&vec!["carol".to_string()], Event::from_gitlab("Merge Request Hook", open).unwrap().unwrap().get_names());
+        let unrelated = br#"{"object_attributes":{"iid":5,"action":"update"},"changes":{"title":{}}}"#;
+        assert_eq!(None, Event::from_gitlab("Merge Request Hook", unrelated).unwrap());
+    }
+}
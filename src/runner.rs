@@ -0,0 +1,240 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use std::{collections::VecDeque, io::{Read, Write}, process::{Command, ExitStatus, Stdio}, sync::{Arc, Mutex}, thread::JoinHandle, time::{Duration, Instant}};
+
+use crate::{cmd::{CMDCommand, CMD, DEFAULT_TITLE}, config, util::time_utils};
+
+/// exit code of a command that could not be started, same as a shell uses
+const EXIT_NOT_STARTED: i32 = 127;
+
+/// last lines of the output of a command
+struct TailBuffer {
+    lines: VecDeque<String>,
+    max_lines: usize,
+}
+
+impl TailBuffer {
+    fn new(max_lines: usize) -> TailBuffer {
+        TailBuffer {
+            lines: VecDeque::with_capacity(max_lines),
+            max_lines,
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.max_lines == 0 {
+            return;
+        }
+        if self.lines.len() == self.max_lines {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    fn text(&self) -> String {
+        self.lines.iter().cloned().collect::<Vec<String>>().join("\n")
+    }
+}
+
+/// move complete lines out of `partial`
+///
+/// a line rewritten by `\r` (like a progress bar) only keeps the text shown last
+fn take_lines(partial: &mut Vec<u8>) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    while let Some(pos) = partial.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = partial.drain(..=pos).collect();
+        lines.push(clean_line(&line));
+    }
+    lines
+}
+
+fn clean_line(line: &[u8]) -> String {
+    let line: String = String::from_utf8_lossy(line).to_string();
+    let line: &str = line.trim_end_matches(['\r', '\n']);
+    line.rsplit('\r').next().unwrap_or_default().to_string()
+}
+
+/// copy everything from `reader` to `writer` as it comes, and keep its last lines in `tail`
+fn tee<R, W>(mut reader: R, mut writer: W, tail: Arc<Mutex<TailBuffer>>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut buffer: [u8; 8192] = [0u8; 8192];
+        let mut partial: Vec<u8> = Vec::new();
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = writer.write_all(&buffer[..n]);
+                    let _ = writer.flush();
+                    partial.extend_from_slice(&buffer[..n]);
+                    let lines: Vec<String> = take_lines(&mut partial);
+                    if let Ok(mut tail) = tail.lock() {
+                        lines.into_iter().for_each(|line| tail.push(line));
+                    }
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        if !partial.is_empty() {
+            if let Ok(mut tail) = tail.lock() {
+                tail.push(clean_line(&partial));
+            }
+        }
+    })
+}
+
+/// exit code of the command, a command killed by a signal exits with 128 + signal like in a shell
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    config::EXIT_ALL_FAILED
+}
+
+/// quote args containing whitespace so the command line can be copied back into a shell
+fn command_line(command: &[String]) -> String {
+    command.iter()
+        .map(|arg| if arg.is_empty() || arg.contains(char::is_whitespace) { format!("'{}'", arg) } else { arg.to_string() })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// run the command of `bark run`, streaming its output
+///
+/// return the cmd to send as notification (None if nothing should be sent) and the exit code of the command
+pub fn run(cmd: &CMD) -> (Option<CMD>, i32) {
+    let Some(CMDCommand::Run { only_on_failure, failure_level, tail, command }) = &cmd.command else {
+        return (None, config::EXIT_INVALID_INPUT);
+    };
+    let command_line: String = command_line(command);
+    let tail_buffer: Arc<Mutex<TailBuffer>> = Arc::new(Mutex::new(TailBuffer::new(*tail)));
+
+    let start: Instant = Instant::now();
+    let child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let (code, detail) = match child {
+        Ok(mut child) => {
+            let stdout: JoinHandle<()> = tee(child.stdout.take().unwrap(), std::io::stdout(), tail_buffer.clone());
+            let stderr: JoinHandle<()> = tee(child.stderr.take().unwrap(), std::io::stderr(), tail_buffer.clone());
+            let status = child.wait();
+            let _ = stdout.join();
+            let _ = stderr.join();
+            match status {
+                Ok(status) => (exit_code(status), None),
+                Err(e) => (config::EXIT_ALL_FAILED, Some(format!("wait for command failed: {}", e))),
+            }
+        },
+        Err(e) => {
+            eprintln!("run {} failed: {}", command_line, e);
+            (EXIT_NOT_STARTED, Some(format!("command not started: {}", e)))
+        }
+    };
+    let elapsed: Duration = start.elapsed();
+
+    let failed: bool = code != 0;
+    if *only_on_failure && !failed {
+        return (None, code);
+    }
+
+    let mut body: String = format!("exit code: {}\nelapsed: {}", code, time_utils::format_duration(elapsed));
+    if let Some(detail) = detail {
+        body = format!("{}\n{}", body, detail);
+    }
+    let output: String = tail_buffer.lock().map(|t| t.text()).unwrap_or_default();
+    if !output.is_empty() {
+        body = format!("{}\n\n{}", body, output);
+    }
+
+    let mut notify: CMD = cmd.clone();
+    notify.title = title(&cmd.title, &command_line, failed);
+    notify.msg = Some(body);
+    if failed {
+        if let Some(level) = failure_level {
+            notify.level = level.to_string();
+        }
+    }
+    (Some(notify), code)
+}
+
+/// title telling how the command ended, after the title passed by -t if any
+fn title(given: &str, command_line: &str, failed: bool) -> String {
+    let outcome: &str = if failed { "failed" } else { "succeeded" };
+    if given == DEFAULT_TITLE {
+        format!("{} {}", command_line, outcome)
+    } else {
+        format!("{}: {} {}", given, command_line, outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_lines() {
+        let mut partial: Vec<u8> = b"line1\nline2\r\n10%\r50%\r100%\nhalf".to_vec();
+        assert_eq!(vec!["line1", "line2", "100%"], take_lines(&mut partial));
+        assert_eq!(b"half".to_vec(), partial);
+    }
+
+    #[test]
+    fn test_tail_buffer() {
+        let mut tail = TailBuffer::new(2);
+        tail.push("1".to_string());
+        tail.push("2".to_string());
+        tail.push("3".to_string());
+        assert_eq!("2\n3", tail.text());
+
+        let mut tail = TailBuffer::new(0);
+        tail.push("1".to_string());
+        assert_eq!("", tail.text());
+    }
+
+    #[test]
+    fn test_title() {
+        assert_eq!("make release failed", title(DEFAULT_TITLE, "make release", true));
+        assert_eq!("nightly backup: ./backup.sh succeeded", title("nightly backup", "./backup.sh", false));
+    }
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!("make -j4 'a b' ''", command_line(&["make".to_string(), "-j4".to_string(), "a b".to_string(), "".to_string()]));
+    }
+}
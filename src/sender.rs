@@ -21,62 +21,243 @@
 // SOFTWARE.
 
 
+use std::{collections::HashMap, time::{Duration, Instant}};
+
 use bark_dev::{bark::Bark, msg::Msg};
 
-use crate::{module::run_file::RunFile, util::file_utils};
+use crate::{apns::{self, ApnsResponse}, cmd::CMD, module::{apns_config::{ApnsConfig, Credential}, dry_run::DryRun, encryption::Encryption, retry_policy::RetryPolicy, run_file::RunFile, send_result::{SendResult, Status}, token_cache::TokenCache}, util::file_utils};
 
+/// seconds a cached token is reused for
+///
+/// APNs accepts a token for an hour and refuses a new one within 20 minutes, `Bark` also still reuses a token this old
+const TOKEN_MAX_AGE: u64 = 40 * 60;
 
 pub struct Sender {
     run_file_path: String,
-    bark: Bark
+    token_cache_path: String,
+    bark: Bark,
+    retry_policy: RetryPolicy
 }
 
 impl Sender {
 
     pub fn new(run_file_path: String) -> Self {
-        let rf: RunFile = Self::run_file_inner(&run_file_path);
-        if let Some(token) = rf.get_token() {
-            let bark = Bark::born(token.get_refresh_at(), token.get_token().to_string());
-            Self {
-                run_file_path,
-                bark
-            }
-        } else {
-            Self {
-                run_file_path,
-                bark: Bark::new()
+        let token_cache_path: String = file_utils::token_cache_path();
+        match file_utils::read_token_cache_from_file(&token_cache_path).get_token() {
+            // `Bark::born` prints a warning to stdout for an expired token, skip it so stdout stays clean
+            Some(token) if token.get_refresh_at() + TOKEN_MAX_AGE > Self::now() => {
+                let bark = Bark::born(token.get_refresh_at(), token.get_token().to_string());
+                Self {
+                    run_file_path,
+                    token_cache_path,
+                    bark,
+                    retry_policy: RetryPolicy::default()
+                }
+            },
+            _ => {
+                Self {
+                    run_file_path,
+                    token_cache_path,
+                    bark: Bark::new(),
+                    retry_policy: RetryPolicy::default()
+                }
             }
         }
-   }
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
     
-    fn run_file(&self) -> RunFile {
-       Self::run_file_inner(&self.run_file_path)
+    fn run_file(&self) -> Result<RunFile, String> {
+        file_utils::read_runfile_from_file(&self.run_file_path)
     }
 
-    fn run_file_inner(path: &str) -> RunFile {
-        file_utils::read_runfile_from_file(path)
+    fn token_cache(&self) -> TokenCache {
+        file_utils::read_token_cache_from_file(&self.token_cache_path)
+    }
+
+    /// resolve receivers and render the request `send` would make, nothing is sent and the run file is left untouched
+    ///
+    /// a device whose user has an own encryption gets its own payload
+    ///
+    /// return an error if the run file does not parse or the receivers can not be resolved
+    pub fn dry_run(&self, options: &CMD) -> Result<DryRun, String> {
+        let run_file: RunFile = self.run_file()?;
+        let alias_devices: HashMap<String, String> = run_file.translate_to_real_devices(&options.receiver)?;
+        let mut alias_devices: Vec<(String, String)> = alias_devices.into_iter().collect();
+        alias_devices.sort();
+
+        let encryption: Option<Encryption> = options.encryption();
+        let mut devices: Vec<(String, String, bool, Option<String>)> = Vec::new();
+        for (alias, device) in alias_devices {
+            let valid: bool = device.len() == 64;
+            let own: Option<Encryption> = Self::encryption_of(&run_file, &alias, &device, &encryption);
+            let payload: Option<String> = if valid && own != encryption {
+                Some(options.to_payload(own.as_ref()).map_err(|e| format!("encrypt for {} failed: {}", alias, e))?)
+            } else {
+                None
+            };
+            devices.push((alias, device, valid, payload));
+        }
+
+        let apns_config: ApnsConfig = run_file.get_apns().merge(&options.apns);
+        let credential: Option<Credential> = apns_config.credential()?;
+        Ok(DryRun::new(
+            apns_config.environment().host(),
+            apns::headers(&options.to_msg(), Self::topic(credential.as_ref())),
+            &options.to_payload(encryption.as_ref())?,
+            devices,
+        ))
     }
 
-    pub fn send(&mut self, msg: &Msg, devices: &Vec<String>) {
-        let alias_devices: std::collections::HashMap<String, String> = self.run_file().translate_to_real_devices(&devices);
-        let devices = alias_devices
-            .iter()
-            .filter(|(_,v)| v.len() == 64)    // bark device_id len is 64
-            .map(|(_alias, real_device)| real_device.to_string())
-            .collect::<Vec<String>>();
+    /// send the msg of `options` to its receivers, encrypted for every device as its user wants
+    ///
+    /// return the result of every receiver, or an error if the run file does not parse or the receivers can not be resolved
+    pub fn send(&mut self, options: &CMD) -> Result<Vec<SendResult>, String> {
+        let run_file: RunFile = self.run_file()?;
+        let alias_devices: HashMap<String, String> = run_file.translate_to_real_devices(&options.receiver)?;
+        let mut alias_devices: Vec<(String, String)> = alias_devices.into_iter().collect();
+        alias_devices.sort();
 
-        for err_device in alias_devices.iter().filter(|(k,v)| k.eq(v) && v.len() != 64).map(|(_, v)| v) {
-            eprintln!("receiver: [{}], formatter may be invalid", err_device);
+        // bark device_id len is 64, devices sharing an encryption share a msg
+        let encryption: Option<Encryption> = options.encryption();
+        let mut batches: Vec<(Option<Encryption>, Vec<String>)> = Vec::new();
+        for (alias, device) in alias_devices.iter().filter(|(_, device)| device.len() == 64) {
+            let own: Option<Encryption> = Self::encryption_of(&run_file, alias, device, &encryption);
+            match batches.iter_mut().find(|(e, _)| *e == own) {
+                Some((_, devices)) => devices.push(device.to_string()),
+                None => batches.push((own, vec![device.to_string()])),
+            }
         }
 
-        let send_result: Option<Vec<String>> = self.bark.send(msg, devices);
-        let (time_stamp, token) = self.bark.token();
-        self.run_file().set_token(time_stamp, token.as_str());
+        let apns_config: ApnsConfig = run_file.get_apns().merge(&options.apns);
+        let credential: Option<Credential> = apns_config.credential()?;
+        let host: &str = apns_config.environment().host();
+
+        let msg: Msg = options.to_msg();
+        let mut responses: HashMap<String, ApnsResponse> = HashMap::new();
+        let mut attempts: HashMap<String, u32> = HashMap::new();
+        for (own, devices) in batches {
+            match options.to_payload(own.as_ref()) {
+                Ok(payload) => {
+                    let (batch_responses, batch_attempts) = self.send_with_retry(&msg, &payload, host, credential.as_ref(), devices);
+                    responses.extend(batch_responses);
+                    attempts.extend(batch_attempts);
+                },
+                // a key of the run file the msg can not be encrypted with, nothing is sent to its devices
+                Err(e) => {
+                    for device in devices {
+                        responses.insert(device, ApnsResponse { status: None, reason: Some(format!("encrypt failed: {}", e)), apns_id: None });
+                    }
+                },
+            }
+        }
 
-        if let Some(failed) =  send_result {
-            failed.iter().for_each(|device| {
-                eprintln!("Send to {} failed", alias_devices.get(device).unwrap());
-            });
+        let mut results: Vec<SendResult> = Vec::new();
+        for (alias, device) in alias_devices.iter() {
+            if device.len() != 64 {
+                eprintln!("receiver: [{}], formatter may be invalid", alias);
+                results.push(SendResult::new(alias, device, Status::Invalid, None, Some("InvalidDeviceToken".to_string()), None, 0));
+                continue;
+            }
+            let attempts: u32 = attempts.get(device).copied().unwrap_or(0);
+            match responses.get(device) {
+                Some(resp) => {
+                    let status: Status = if resp.is_success() { Status::Delivered } else { Status::Failed };
+                    results.push(SendResult::new(alias, device, status, resp.status, resp.reason.clone(), resp.apns_id.clone(), attempts));
+                },
+                None => {
+                    results.push(SendResult::new(alias, device, Status::Failed, None, Some("no response".to_string()), None, attempts));
+                }
+            }
         }
+
+        Ok(results)
     }
-}
\ No newline at end of file
+
+    /// the encryption of the user `alias`, or of the user of `device` when a device token is passed as is,
+    /// `default` if the user has none or there is no such user
+    fn encryption_of(run_file: &RunFile, alias: &str, device: &str, default: &Option<Encryption>) -> Option<Encryption> {
+        run_file.get_user_info_by_name(alias)
+            .or_else(|| run_file.get_user_info_by_device_token(device))
+            .and_then(|u| u.get_encryption().cloned())
+            .or_else(|| default.clone())
+    }
+
+    /// bundle id of the app the credential pushes to
+    fn topic(credential: Option<&Credential>) -> &str {
+        credential.map(Credential::get_topic).unwrap_or(apns::BARK_APP_TOPIC)
+    }
+
+    /// token of the credential, None for the built-in one which `Bark` creates or reuses
+    ///
+    /// the token of an own credential is reused from the token cache until `TOKEN_MAX_AGE`
+    fn token(&mut self, credential: Option<&Credential>) -> Result<(u64, String), String> {
+        let Some(credential) = credential else {
+            return Ok(self.bark.force_refresh_token());
+        };
+        let now: u64 = Self::now();
+        match self.token_cache().get_credential_token(&credential.id()) {
+            Some(token) if token.get_refresh_at() + TOKEN_MAX_AGE > now => Ok((token.get_refresh_at(), token.get_token().to_string())),
+            _ => Ok((now, apns::sign_token(credential, now)?)),
+        }
+    }
+
+    /// send to `devices` on `host`, then resend to the ones failed with a retriable error as the retry policy allows
+    ///
+    /// return the last response and the attempt count of every device
+    fn send_with_retry(&mut self, msg: &Msg, payload: &str, host: &str, credential: Option<&Credential>, devices: Vec<String>) -> (HashMap<String, ApnsResponse>, HashMap<String, u32>) {
+        let mut responses: HashMap<String, ApnsResponse> = HashMap::new();
+        let mut attempts: HashMap<String, u32> = HashMap::new();
+        if devices.is_empty() {
+            return (responses, attempts);
+        }
+
+        let start: Instant = Instant::now();
+        let mut pending: Vec<String> = devices;
+        let mut retry: u32 = 0;
+        loop {
+            // the token is created or reused and cached into the token cache, keyed by its credential
+            let (time_stamp, token) = match self.token(credential) {
+                Ok(token) => token,
+                Err(e) => {
+                    for device in pending {
+                        responses.insert(device, ApnsResponse { status: None, reason: Some(e.clone()), apns_id: None });
+                    }
+                    break;
+                },
+            };
+            responses.extend(apns::send(msg, payload, host, Self::topic(credential), &token, &pending));
+            match credential {
+                Some(credential) => self.token_cache().set_credential_token(&credential.id(), time_stamp, token.as_str()),
+                None => self.token_cache().set_token(time_stamp, token.as_str()),
+            }
+            for device in pending.iter() {
+                *attempts.entry(device.to_string()).or_insert(0) += 1;
+            }
+
+            pending.retain(|device| responses.get(device).is_some_and(|r| !r.is_success() && r.is_retriable()));
+            if pending.is_empty() {
+                break;
+            }
+
+            retry += 1;
+            let delay: Duration = self.retry_policy.delay(retry);
+            if !self.retry_policy.allows(retry, start.elapsed(), delay) {
+                break;
+            }
+            eprintln!("retry {} device(s) in {:.1}s ({}/{})", pending.len(), delay.as_secs_f64(), retry, self.retry_policy.get_retries());
+            std::thread::sleep(delay);
+        }
+
+        (responses, attempts)
+    }
+}
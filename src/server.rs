@@ -0,0 +1,252 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::io::Read;
+
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+
+use crate::{cmd::{ServeArgs, CMD}, config, module::{alertmanager::Notification, push_request::PushRequest, webhook::{self, Event}, run_file::RunFile, send_result::SendResult}, sender::Sender, util::{file_utils, log_utils::log}};
+
+/// requests with a larger body are refused
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// answer to a request, in the json format of the Bark server
+struct Reply {
+    code: u16,
+    message: String,
+}
+
+impl Reply {
+    fn ok(message: &str) -> Reply {
+        Reply { code: 200, message: message.to_string() }
+    }
+
+    fn error(code: u16, message: &str) -> Reply {
+        Reply { code, message: message.to_string() }
+    }
+
+    fn to_json(&self) -> String {
+        json!({
+            "code": self.code,
+            "message": self.message,
+            "timestamp": chrono::Local::now().timestamp(),
+        }).to_string()
+    }
+}
+
+/// serve the Bark server push API and webhooks, options of `cmd` are the defaults of every push
+///
+/// requests are handled one by one, so the token in the token cache is never refreshed twice at once
+pub fn run(cmd: &CMD, args: &ServeArgs) -> i32 {
+    let server: Server = match Server::http(&args.listen) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("listen on {} failed: {}", args.listen, e);
+            return config::EXIT_INVALID_INPUT;
+        }
+    };
+    log(&format!("listening on http://{}", args.listen));
+
+    for mut request in server.incoming_requests() {
+        let reply: Reply = handle(cmd, args, &mut request);
+        // the path holds the msg itself, keep it out of the log
+        log(&format!("{} {} {}", request.method(), reply.code, reply.message));
+
+        let response = Response::from_string(reply.to_json())
+            .with_status_code(reply.code)
+            .with_header(Header::from_bytes("Content-Type", "application/json; charset=utf-8").unwrap());
+        if let Err(e) = request.respond(response) {
+            log(&format!("respond failed: {}", e));
+        }
+    }
+    config::EXIT_ALL_DELIVERED
+}
+
+fn handle(cmd: &CMD, args: &ServeArgs, request: &mut Request) -> Reply {
+    let url: Url = match Url::parse(&format!("http://localhost{}", request.url())) {
+        Ok(url) => url,
+        Err(e) => return Reply::error(400, &format!("invalid url: {}", e)),
+    };
+    let mut segments: Vec<String> = url.path_segments()
+        .map(|s| s.map(|s| percent_decode_str(s).decode_utf8_lossy().to_string()).collect())
+        .unwrap_or_default();
+    if segments.last().is_some_and(|s| s.is_empty()) {
+        segments.pop();
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    if let Err(e) = request.as_reader().take(MAX_BODY_SIZE as u64 + 1).read_to_end(&mut body) {
+        return Reply::error(400, &format!("read body failed: {}", e));
+    }
+    if body.len() > MAX_BODY_SIZE {
+        return Reply::error(413, "body is too large");
+    }
+    let content_type: String = header(request, "Content-Type").to_ascii_lowercase();
+    let is_json: bool = content_type.starts_with("application/json");
+    let is_form: bool = content_type.starts_with("application/x-www-form-urlencoded");
+
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+    let push: Result<PushRequest, String> = match (request.method(), segments.as_slice()) {
+        (Method::Get, ["ping"]) => return Reply::ok("pong"),
+        (Method::Get, ["healthz"]) => return Reply::ok("ok"),
+        (Method::Post, ["alertmanager"]) => return alertmanager(cmd, args, &body, None),
+        (Method::Post, ["alertmanager", key]) => return alertmanager(cmd, args, &body, Some(key)),
+        (Method::Post, ["github"]) => return github(cmd, args, request, &body, None),
+        (Method::Post, ["github", key]) => return github(cmd, args, request, &body, Some(key)),
+        (Method::Post, ["gitlab"]) => return gitlab(cmd, args, request, &body, None),
+        (Method::Post, ["gitlab", key]) => return gitlab(cmd, args, request, &body, Some(key)),
+        (Method::Post, ["push"]) if is_json => PushRequest::from_json(&body),
+        (Method::Post, ["push"]) => Ok(PushRequest::default()),
+        (Method::Get | Method::Post, [_, ..]) => PushRequest::from_path(&segments.iter().map(|s| s.to_string()).collect::<Vec<String>>()),
+        _ => return Reply::error(404, "not found"),
+    };
+
+    // options in the query and in a form come on top of the path or json
+    let push: Result<PushRequest, String> = push.and_then(|mut push| {
+        let form: Vec<(String, String)> = if is_form { url::form_urlencoded::parse(&body).into_owned().collect() } else { Vec::new() };
+        for (name, value) in url.query_pairs().into_owned().chain(form) {
+            push.set_param(&name, &value)?;
+        }
+        Ok(push)
+    });
+
+    match push.and_then(|push| push.to_cmd(cmd)) {
+        Ok(options) => send(&options),
+        Err(e) => Reply::error(400, &e),
+    }
+}
+
+/// send every alert of the webhook, to the key of the url, to its labels or to -r
+fn alertmanager(cmd: &CMD, args: &ServeArgs, body: &[u8], key: Option<&str>) -> Reply {
+    let notification: Notification = match Notification::from_json(body) {
+        Ok(notification) => notification,
+        Err(e) => return Reply::error(400, &e),
+    };
+
+    let run_file: RunFile = match file_utils::read_runfile_from_file(&cmd.config) {
+        Ok(run_file) => run_file,
+        Err(e) => return Reply::error(500, &e),
+    };
+    let known = |name: &str| run_file.get_user_info_by_name(name).is_some() || run_file.get_group_info_by_name(name).is_some();
+    let replies: Vec<Reply> = notification.get_alerts().iter().map(|alert| {
+        let receivers: Vec<String> = match key {
+            Some(key) => vec![key.to_string()],
+            None => match notification.receivers(alert, &args.alert_labels, known) {
+                receivers if receivers.is_empty() => cmd.receiver.clone(),
+                receivers => receivers,
+            },
+        };
+        if receivers.is_empty() {
+            return Reply::error(400, "no receiver for the alert, set one in the url, in --alert-labels or by -r");
+        }
+        send(&notification.to_cmd(alert, cmd, receivers, args.alert_resolved))
+    }).collect();
+
+    // the worst reply wins, Alertmanager retries on 5xx
+    match replies.iter().map(|r| r.code).max() {
+        None => Reply::ok("no alert"),
+        Some(200) => Reply::ok("success"),
+        Some(code) => Reply::error(code, &replies.iter()
+            .filter(|r| r.code != 200)
+            .map(|r| r.message.as_str())
+            .collect::<Vec<&str>>()
+            .join("; ")),
+    }
+}
+
+fn github(cmd: &CMD, args: &ServeArgs, request: &Request, body: &[u8], key: Option<&str>) -> Reply {
+    let Some(secret) = &args.github_secret else {
+        return Reply::error(403, "github webhook is disabled, set --github-secret");
+    };
+    if !webhook::verify_github_signature(secret, body, &header(request, "X-Hub-Signature-256")) {
+        return Reply::error(401, "invalid signature");
+    }
+    match header(request, "X-GitHub-Event").as_str() {
+        "ping" => Reply::ok("pong"),
+        event => notify(cmd, Event::from_github(event, body), key),
+    }
+}
+
+fn gitlab(cmd: &CMD, args: &ServeArgs, request: &Request, body: &[u8], key: Option<&str>) -> Reply {
+    let Some(token) = &args.gitlab_token else {
+        return Reply::error(403, "gitlab webhook is disabled, set --gitlab-token");
+    };
+    if !webhook::verify_gitlab_token(token, &header(request, "X-Gitlab-Token")) {
+        return Reply::error(401, "invalid token");
+    }
+    notify(cmd, Event::from_gitlab(&header(request, "X-Gitlab-Event"), body), key)
+}
+
+/// send a webhook event to the key of the url, to the mapping of its names or to -r
+fn notify(cmd: &CMD, event: Result<Option<Event>, String>, key: Option<&str>) -> Reply {
+    let event: Event = match event {
+        Ok(Some(event)) => event,
+        Ok(None) => return Reply::ok("ignored"),
+        Err(e) => return Reply::error(400, &e),
+    };
+    let receivers: Vec<String> = match key {
+        Some(key) => vec![key.to_string()],
+        None => match file_utils::read_runfile_from_file(&cmd.config).map(|run_file| run_file.mapped_receivers(event.get_names())) {
+            Ok(receivers) if receivers.is_empty() => cmd.receiver.clone(),
+            Ok(receivers) => receivers,
+            Err(e) => return Reply::error(500, &e),
+        },
+    };
+    if receivers.is_empty() {
+        return Reply::error(400, &format!("no receiver for {}, add a mapping or set one in the url or by -r", event.get_names().join(", ")));
+    }
+    send(&event.to_cmd(cmd, receivers))
+}
+
+/// value of a request header, empty if missing
+fn header(request: &Request, name: &'static str) -> String {
+    request.headers().iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_string())
+        .unwrap_or_default()
+}
+
+fn send(options: &CMD) -> Reply {
+    match file_utils::read_runfile_from_file(&options.config) {
+        Ok(run_file) => if let Err(e) = run_file.translate_to_real_devices(&options.receiver) {
+            return Reply::error(400, &e);
+        },
+        Err(e) => return Reply::error(500, &e),
+    }
+
+    let mut sender: Sender = Sender::new(options.config.clone());
+    sender.set_retry_policy(options.retry_policy());
+    let results: Vec<SendResult> = match sender.send(options) {
+        Ok(results) => results,
+        Err(e) => return Reply::error(400, &e),
+    };
+
+    let failures: String = results.iter().filter_map(|r| r.failure()).collect::<Vec<String>>().join("; ");
+    match SendResult::exit_code(&results) {
+        config::EXIT_ALL_DELIVERED => Reply::ok("success"),
+        config::EXIT_INVALID_INPUT => Reply::error(400, &failures),
+        _ => Reply::error(500, &failures),
+    }
+}
@@ -21,4 +21,10 @@
 // SOFTWARE.
 
 
-pub mod file_utils;
\ No newline at end of file
+pub mod file_utils;
+pub mod msg_utils;
+pub mod time_utils;
+pub mod log_utils;
+pub mod template_utils;
+pub mod crypto_utils;
+
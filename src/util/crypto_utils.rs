@@ -0,0 +1,273 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use openssl::symm::{Cipher, Crypter, Mode};
+
+use crate::module::encryption::{EncryptionMode, EncryptionType, KeyFormat};
+
+/// length of the tag appended to a GCM ciphertext by the Bark app
+const GCM_TAG_LEN: usize = 16;
+/// nonce length of GCM, same as the Bark app
+const GCM_IV_LEN: usize = 12;
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// the openssl cipher of the type and mode, the same one `bark_dev::msg::Msg` picks
+pub fn cipher(enc_type: EncryptionType, mode: EncryptionMode) -> Cipher {
+    match (enc_type, mode) {
+        (EncryptionType::Aes128, EncryptionMode::Cbc) => Cipher::aes_128_cbc(),
+        (EncryptionType::Aes128, EncryptionMode::Ecb) => Cipher::aes_128_ecb(),
+        (EncryptionType::Aes128, EncryptionMode::Gcm) => Cipher::aes_128_gcm(),
+        (EncryptionType::Aes192, EncryptionMode::Cbc) => Cipher::aes_192_cbc(),
+        (EncryptionType::Aes192, EncryptionMode::Ecb) => Cipher::aes_192_ecb(),
+        (EncryptionType::Aes192, EncryptionMode::Gcm) => Cipher::aes_192_gcm(),
+        (EncryptionType::Aes256, EncryptionMode::Cbc) => Cipher::aes_256_cbc(),
+        (EncryptionType::Aes256, EncryptionMode::Ecb) => Cipher::aes_256_ecb(),
+        (EncryptionType::Aes256, EncryptionMode::Gcm) => Cipher::aes_256_gcm(),
+    }
+}
+
+/// key length in bytes of the type
+pub fn key_len(enc_type: EncryptionType) -> usize {
+    match enc_type {
+        EncryptionType::Aes128 => 16,
+        EncryptionType::Aes192 => 24,
+        EncryptionType::Aes256 => 32,
+    }
+}
+
+/// iv length in bytes of the mode, None if it takes no iv
+pub fn iv_len(mode: EncryptionMode) -> Option<usize> {
+    match mode {
+        EncryptionMode::Cbc => Some(16),
+        EncryptionMode::Ecb => None,
+        EncryptionMode::Gcm => Some(GCM_IV_LEN),
+    }
+}
+
+/// random letters and digits, typed into the Bark app as a key or an iv
+pub fn random_text(len: usize) -> Result<String, String> {
+    let mut text: String = String::with_capacity(len);
+    let mut bytes: [u8; 64] = [0u8; 64];
+    while text.len() < len {
+        openssl::rand::rand_bytes(&mut bytes).map_err(|e| e.to_string())?;
+        // 248 is the largest multiple of 62 below 256, dropping larger bytes keeps every char equally likely
+        bytes.iter()
+            .filter(|b| **b < 248)
+            .take(len - text.len())
+            .for_each(|b| text.push(ALPHANUMERIC[*b as usize % ALPHANUMERIC.len()] as char));
+    }
+    Ok(text)
+}
+
+/// the key passed by `--key`, `--key-file` or `--key-env`, None if none of them is passed
+///
+/// `--key -` asks for the key at a hidden prompt, so it is kept out of the shell history and the process list
+pub fn read_key(key: Option<&str>, key_file: Option<&str>, key_env: Option<&str>) -> Result<Option<String>, String> {
+    let key: String = match (key, key_file, key_env) {
+        (Some("-"), _, _) => rpassword::prompt_password("encryption key: ")
+            .map_err(|e| format!("read key from the terminal failed: {}", e))?,
+        (Some(key), _, _) => key.to_string(),
+        (None, Some(path), _) => std::fs::read_to_string(path)
+            .map_err(|e| format!("read key from {} failed: {}", path, e))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        (None, None, Some(name)) => std::env::var(name)
+            .map_err(|_| format!("environment variable {} of the key is not set", name))?,
+        (None, None, None) => return Ok(None),
+    };
+    if key.is_empty() {
+        return Err("key is empty".to_string());
+    }
+    Ok(Some(key))
+}
+
+/// the key bytes of `key` written in `format`
+pub fn decode_key(key: &str, format: KeyFormat) -> Result<Vec<u8>, String> {
+    match format {
+        KeyFormat::Text => Ok(key.as_bytes().to_vec()),
+        KeyFormat::Hex => {
+            if !key.len().is_multiple_of(2) || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("key is not hex".to_string());
+            }
+            Ok((0..key.len()).step_by(2).map(|i| u8::from_str_radix(&key[i..i + 2], 16).unwrap()).collect())
+        },
+        KeyFormat::Base64 => openssl::base64::decode_block(key.trim()).map_err(|_| "key is not base64".to_string()),
+    }
+}
+
+/// check the key and iv fit the type and mode
+pub fn check(enc_type: EncryptionType, mode: EncryptionMode, key: &[u8], iv: Option<&[u8]>) -> Result<(), String> {
+    check_key(enc_type, key)?;
+    check_iv(mode, iv)
+}
+
+pub fn check_key(enc_type: EncryptionType, key: &[u8]) -> Result<(), String> {
+    if key.len() != key_len(enc_type) {
+        return Err(format!("key of {} must be {} bytes, got {}", enc_type, key_len(enc_type), key.len()));
+    }
+    Ok(())
+}
+
+/// cbc and gcm take an iv of their length, ecb none
+pub fn check_iv(mode: EncryptionMode, iv: Option<&[u8]>) -> Result<(), String> {
+    match (iv_len(mode), iv) {
+        (Some(len), Some(iv)) if iv.len() != len => Err(format!("iv of {} mode must be {} bytes, got {}", mode, len, iv.len())),
+        (Some(_), None) => Err(format!("iv is required by {} mode", mode)),
+        (None, Some(_)) => Err(format!("{} mode takes no iv", mode)),
+        _ => Ok(()),
+    }
+}
+
+/// encrypt like the sender, with PKCS7 padding and, as `bark_dev` does, without a GCM tag
+pub fn encrypt(enc_type: EncryptionType, mode: EncryptionMode, key: &[u8], iv: Option<&[u8]>, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    check(enc_type, mode, key, iv)?;
+    let cipher: Cipher = cipher(enc_type, mode);
+    let mut crypter: Crypter = Crypter::new(cipher, Mode::Encrypt, key, iv).map_err(|e| e.to_string())?;
+    crypter.pad(true);
+    let mut buffer: Vec<u8> = vec![0; plaintext.len() + cipher.block_size()];
+    let count: usize = crypter.update(plaintext, &mut buffer).map_err(|e| e.to_string())?;
+    let rest: usize = crypter.finalize(&mut buffer[count..]).map_err(|e| e.to_string())?;
+    buffer.truncate(count + rest);
+    Ok(buffer)
+}
+
+/// decrypt a ciphertext of the sender or of the Bark server API
+///
+/// a GCM ciphertext is first taken as ending with its tag like the Bark app expects,
+/// if the tag does not match it is decrypted as untagged, the way `bark_dev` sends it
+///
+/// return the plaintext and whether a GCM tag was verified
+pub fn decrypt(enc_type: EncryptionType, mode: EncryptionMode, key: &[u8], iv: Option<&[u8]>, ciphertext: &[u8]) -> Result<(Vec<u8>, bool), String> {
+    check(enc_type, mode, key, iv)?;
+    let cipher: Cipher = cipher(enc_type, mode);
+    if mode == EncryptionMode::Gcm {
+        if ciphertext.len() >= GCM_TAG_LEN {
+            let (data, tag) = ciphertext.split_at(ciphertext.len() - GCM_TAG_LEN);
+            if let Ok(plaintext) = openssl::symm::decrypt_aead(cipher, key, iv, &[], data, tag) {
+                return Ok((plaintext, true));
+            }
+        }
+        // without a tag to verify, GCM decrypts like a stream cipher
+        let mut crypter: Crypter = Crypter::new(cipher, Mode::Decrypt, key, iv).map_err(|e| e.to_string())?;
+        let mut buffer: Vec<u8> = vec![0; ciphertext.len() + cipher.block_size()];
+        let count: usize = crypter.update(ciphertext, &mut buffer).map_err(|e| e.to_string())?;
+        buffer.truncate(count);
+        return Ok((buffer, false));
+    }
+
+    let mut crypter: Crypter = Crypter::new(cipher, Mode::Decrypt, key, iv).map_err(|e| e.to_string())?;
+    crypter.pad(true);
+    let mut buffer: Vec<u8> = vec![0; ciphertext.len() + cipher.block_size()];
+    let count: usize = crypter.update(ciphertext, &mut buffer).map_err(|e| e.to_string())?;
+    let rest: usize = crypter.finalize(&mut buffer[count..])
+        .map_err(|_| "decrypt failed, the key, iv, type or mode is not the one it was encrypted with".to_string())?;
+    buffer.truncate(count + rest);
+    Ok((buffer, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::encryption::{Encryption, IvPolicy};
+
+    #[test]
+    fn test_random_text() {
+        for len in [0, 16, 24, 32, 100] {
+            let text: String = random_text(len).unwrap();
+            assert_eq!(len, text.len());
+            assert!(text.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+        assert_ne!(random_text(32).unwrap(), random_text(32).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let plaintext: &[u8] = b"{\"body\":\"hello world\"}";
+        for enc_type in [EncryptionType::Aes128, EncryptionType::Aes192, EncryptionType::Aes256] {
+            for mode in [EncryptionMode::Cbc, EncryptionMode::Ecb, EncryptionMode::Gcm] {
+                let key: String = random_text(key_len(enc_type)).unwrap();
+                let iv: Option<String> = iv_len(mode).map(|len| random_text(len).unwrap());
+                let iv: Option<&[u8]> = iv.as_deref().map(str::as_bytes);
+                let ciphertext: Vec<u8> = encrypt(enc_type, mode, key.as_bytes(), iv, plaintext).unwrap();
+                assert_eq!((plaintext.to_vec(), false), decrypt(enc_type, mode, key.as_bytes(), iv, &ciphertext).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_decrypt_sender_payload() {
+        let key: &str = "123456789012345678901234";
+        let encryption: Encryption = Encryption::new(EncryptionType::Aes192, EncryptionMode::Gcm, key, KeyFormat::Text, IvPolicy::Fixed("abcdefghijkl".to_string()));
+        let payload: serde_json::Value = serde_json::from_str(&encryption.encrypt("{\"aps\":{}}", "hello world").unwrap()).unwrap();
+        let ciphertext: Vec<u8> = openssl::base64::decode_block(payload["ciphertext"].as_str().unwrap()).unwrap();
+
+        let (plaintext, _) = decrypt(EncryptionType::Aes192, EncryptionMode::Gcm, key.as_bytes(), Some(b"abcdefghijkl"), &ciphertext).unwrap();
+        assert_eq!(b"{\"body\":\"hello world\"}".to_vec(), plaintext);
+    }
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(Ok(b"key".to_vec()), decode_key("key", KeyFormat::Text));
+        assert_eq!(Ok(vec![0x00, 0xab, 0xff]), decode_key("00abFF", KeyFormat::Hex));
+        assert_eq!(Ok(b"key".to_vec()), decode_key("a2V5", KeyFormat::Base64));
+        assert!(decode_key("abc", KeyFormat::Hex).is_err());
+        assert!(decode_key("zz", KeyFormat::Hex).is_err());
+        assert!(decode_key("not base64!", KeyFormat::Base64).is_err());
+    }
+
+    #[test]
+    fn test_read_key() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("bark_key_file_test");
+        std::fs::write(&path, "0123456789abcdef\n").unwrap();
+        assert_eq!(Ok(Some("0123456789abcdef".to_string())), read_key(None, path.to_str(), None));
+        std::fs::write(&path, "\r\n").unwrap();
+        assert_eq!(Err("key is empty".to_string()), read_key(None, path.to_str(), None));
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_key(None, path.to_str(), None).is_err());
+
+        std::env::set_var("BARK_KEY_ENV_TEST", "key from env");
+        assert_eq!(Ok(Some("key from env".to_string())), read_key(None, None, Some("BARK_KEY_ENV_TEST")));
+        assert!(read_key(None, None, Some("BARK_KEY_ENV_TEST_UNSET")).is_err());
+
+        assert_eq!(Ok(Some("key".to_string())), read_key(Some("key"), None, None));
+        assert_eq!(Ok(None), read_key(None, None, None));
+    }
+
+    #[test]
+    fn test_decrypt_tagged_gcm() {
+        let key: &[u8] = b"1234567890123456";
+        let iv: &[u8] = b"abcdefghijkl";
+        let mut tag: [u8; GCM_TAG_LEN] = [0; GCM_TAG_LEN];
+        let mut ciphertext: Vec<u8> = openssl::symm::encrypt_aead(Cipher::aes_128_gcm(), key, Some(iv), &[], b"{\"body\":\"hi\"}", &mut tag).unwrap();
+        ciphertext.extend_from_slice(&tag);
+        assert_eq!((b"{\"body\":\"hi\"}".to_vec(), true), decrypt(EncryptionType::Aes128, EncryptionMode::Gcm, key, Some(iv), &ciphertext).unwrap());
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(Err("key of aes256 must be 32 bytes, got 10".to_string()), check(EncryptionType::Aes256, EncryptionMode::Ecb, b"0123456789", None));
+        assert_eq!(Err("iv of cbc mode must be 16 bytes, got 12".to_string()), check(EncryptionType::Aes128, EncryptionMode::Cbc, b"1234567890123456", Some(b"abcdefghijkl")));
+        assert_eq!(Err("iv is required by gcm mode".to_string()), check(EncryptionType::Aes128, EncryptionMode::Gcm, b"1234567890123456", None));
+        assert_eq!(Err("ecb mode takes no iv".to_string()), check(EncryptionType::Aes128, EncryptionMode::Ecb, b"1234567890123456", Some(b"x")));
+        assert!(check(EncryptionType::Aes128, EncryptionMode::Ecb, b"1234567890123456", None).is_ok());
+    }
+}
@@ -21,9 +21,9 @@
 // SOFTWARE.
 
 
-use crate::module::run_file::RunFile;
+use crate::{config, module::{job_queue::JobQueue, run_file::RunFile, token_cache::TokenCache}};
 
-use std::path::{Path, PathBuf};
+use std::{ffi::OsString, fs::{DirBuilder, File, OpenOptions}, io::Write, path::{Path, PathBuf}};
 
 /// Resolve run file path templates (e.g. leading `~`, Windows `%VAR%`).
 ///
@@ -103,36 +103,199 @@ fn expand_percent_env_vars(input: &str) -> String {
     out
 }
 
-pub fn write_runfile_to_file(path: &str, content: &RunFile) -> Result<(), std::io::Error> {
-    let resolved_path = resolve_runfile_path(path);
+/// `path` with `.suffix` appended, like "bark.conf" -> "bark.conf.bak"
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
 
-    if let Some(parent) = Path::new(&resolved_path).parent() {
-        std::fs::create_dir_all(parent)?;
+/// create `dir` and its missing parents, on Unix only the owner can enter the ones created
+fn create_dir(dir: &Path) -> Result<(), std::io::Error> {
+    let mut builder: DirBuilder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// options to open a file which, if created, on Unix only the owner can read and write
+fn private_file() -> OpenOptions {
+    let mut options: OpenOptions = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+/// write `content` into a temp file next to `path` and rename it over `path`,
+/// so a reader sees the old content or the new one, never a half written file
+pub fn write_atomic(path: &Path, content: &str) -> Result<(), std::io::Error> {
+    if let Some(parent) = path.parent() {
+        create_dir(parent)?;
     }
 
-    match content.to_string() {
-        Ok(content) => std::fs::write(&resolved_path, content),
-        Err(_) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "parse content failed",
-            ))
+    let tmp: PathBuf = sibling(path, &format!("tmp.{}", std::process::id()));
+    let result: Result<(), std::io::Error> = (|| {
+        let mut file: File = private_file().write(true).create(true).truncate(true).open(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        // the new file gets the permissions of the one it replaces
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&tmp, metadata.permissions())?;
         }
+        std::fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
     }
+    result
 }
 
-pub fn read_runfile_from_file(path: &str) -> RunFile {
+/// take an advisory lock on `<path>.lock`, other bark processes wait for it until the returned file is dropped
+///
+/// the lock file is left in place, removing it would let two processes lock different files
+pub fn lock_file(path: &Path) -> Result<File, std::io::Error> {
+    if let Some(parent) = path.parent() {
+        create_dir(parent)?;
+    }
+    let file: File = private_file().create(true).truncate(false).write(true).open(sibling(path, "lock"))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// write the run file atomically, the version it replaces is kept as `<path>.bak`
+pub fn write_runfile_to_file(path: &str, content: &RunFile) -> Result<(), std::io::Error> {
+    let resolved_path: PathBuf = PathBuf::from(resolve_runfile_path(path));
+
+    let content: String = match content.to_string() {
+        Ok(content) => content,
+        Err(_) => return Err(std::io::Error::other("parse content failed")),
+    };
+    if resolved_path.exists() {
+        std::fs::copy(&resolved_path, sibling(&resolved_path, "bak"))?;
+    }
+    write_atomic(&resolved_path, &content)
+}
+
+/// a missing run file is an empty one, a broken one is an error telling the line and column,
+/// so it is never taken as empty and written over
+pub fn read_runfile_from_file(path: &str) -> Result<RunFile, String> {
     let resolved_path = resolve_runfile_path(path);
 
     match std::fs::read_to_string(&resolved_path) {
-        Ok(content) => {
-            if let Ok(rf) = RunFile::from_string(&content) {
-                RunFile::new(rf, resolved_path)
-            } else {
-                RunFile::new_empty(resolved_path)
-            }
+        Ok(content) => RunFile::from_string(&content)
+            .map(|rf| RunFile::new(rf, resolved_path.clone()))
+            .map_err(|e| format!("parse {} failed: {}", resolved_path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(RunFile::new_empty(resolved_path)),
+        Err(e) => Err(format!("read {} failed: {}", resolved_path, e)),
+    }
+}
+
+/// the mode of the file at `path` if others than its owner can access it,
+/// None if only the owner can, it does not exist or the platform has no such modes
+pub fn shared_mode(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode: u32 = std::fs::metadata(path).ok()?.permissions().mode() & 0o777;
+        (mode & 0o077 != 0).then_some(mode)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// take away what others than the owner can do with the run file, the files bark keeps next to it,
+/// the token cache and the directories they are in, the home directory is left as it is
+///
+/// return what was changed like "/home/me/bark.conf: 0644 -> 0600"
+pub fn fix_permissions(path: &str, token_cache_path: &str) -> Result<Vec<String>, String> {
+    let path: PathBuf = PathBuf::from(resolve_runfile_path(path));
+    let token_cache: PathBuf = PathBuf::from(token_cache_path);
+    let mut targets: Vec<(PathBuf, u32)> = [sibling(&path, "bak"), sibling(&path, "lock"), PathBuf::from(queue_path(&path.to_string_lossy()))]
+        .into_iter()
+        .chain([path.clone(), sibling(&token_cache, "lock"), token_cache.clone()])
+        .map(|file| (file, 0o600))
+        .collect();
+    let home: Option<PathBuf> = std::env::var_os("HOME").map(PathBuf::from);
+    for dir in [path.parent(), token_cache.parent()].into_iter().flatten() {
+        if !dir.as_os_str().is_empty() && home.as_deref() != Some(dir) && !targets.iter().any(|(target, _)| target == dir) {
+            targets.push((dir.to_path_buf(), 0o700));
+        }
+    }
+
+    let mut changed: Vec<String> = Vec::new();
+    for (target, restricted) in targets {
+        let Some(mode) = shared_mode(&target) else {
+            continue;
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&target, std::fs::Permissions::from_mode(restricted))
+                .map_err(|e| format!("change mode of {} failed: {}", target.display(), e))?;
         }
-        Err(_e) => RunFile::new_empty(resolved_path),
+        changed.push(format!("{}: {:04o} -> {:04o}", target.display(), mode, restricted));
+    }
+    Ok(changed)
+}
+
+/// scheduled jobs are kept next to the run file, like "bark.conf" -> "bark.queue"
+pub fn queue_path(runfile_path: &str) -> String {
+    Path::new(&resolve_runfile_path(runfile_path))
+        .with_extension("queue")
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn write_queue_to_file(path: &str, content: &JobQueue) -> Result<(), std::io::Error> {
+    match content.to_string() {
+        Ok(content) => write_atomic(Path::new(path), &content),
+        Err(e) => Err(std::io::Error::other(format!("serialize queue failed: {}", e))),
+    }
+}
+
+/// where cached tokens are kept, `$XDG_CACHE_HOME/bark/token.toml` if it is set on Linux, else the compiled default
+pub fn token_cache_path() -> String {
+    #[cfg(target_os = "linux")]
+    if let Some(cache_home) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| Path::new(dir).is_absolute()) {
+        return PathBuf::from(cache_home).join("bark").join("token.toml").to_string_lossy().to_string();
+    }
+    resolve_runfile_path(config::TOKEN_CACHE_BARK)
+}
+
+pub fn write_token_cache_to_file(path: &str, content: &TokenCache) -> Result<(), std::io::Error> {
+    match content.to_string() {
+        Ok(content) => write_atomic(Path::new(path), &content),
+        Err(e) => Err(std::io::Error::other(format!("serialize token cache failed: {}", e))),
+    }
+}
+
+/// a missing or broken token cache is an empty one, the tokens are signed again
+pub fn read_token_cache_from_file(path: &str) -> TokenCache {
+    match std::fs::read_to_string(path).ok().and_then(|content| TokenCache::from_string(&content).ok()) {
+        Some(cache) => TokenCache::new(cache, path.to_string()),
+        None => TokenCache::new_empty(path.to_string()),
+    }
+}
+
+/// a missing queue file is an empty queue, a broken one is an error so jobs are never dropped silently
+/// read the queue under its lock, other bark processes wait to change it until the returned file is dropped
+pub fn lock_queue(path: &str) -> Result<(File, JobQueue), String> {
+    let lock: File = lock_file(Path::new(path)).map_err(|e| format!("lock {} failed: {}", path, e))?;
+    read_queue_from_file(path).map(|queue| (lock, queue))
+}
+
+pub fn read_queue_from_file(path: &str) -> Result<JobQueue, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => JobQueue::from_string(&content)
+            .map(|queue| JobQueue::new(queue, path.to_string()))
+            .map_err(|e| format!("parse {} failed: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(JobQueue::new_empty(path.to_string())),
+        Err(e) => Err(format!("read {} failed: {}", path, e)),
     }
 }
 
@@ -140,6 +303,11 @@ pub fn read_runfile_from_file(path: &str) -> RunFile {
 mod tests {
     use std::{collections::HashMap, path::PathBuf};
 
+    use chrono::Local;
+    use clap::Parser;
+
+    use crate::{cmd::CMD, module::repeat::CatchUp};
+
     use super::*;
 
     pub fn tmp_path() -> PathBuf {
@@ -154,11 +322,11 @@ mod tests {
 
     #[test]
     fn test_read_from_file() {
-        let mut rf = read_runfile_from_file(tmp_path().to_str().unwrap());
+        let mut rf = read_runfile_from_file(tmp_path().to_str().unwrap()).unwrap();
 
         assert_eq!(
             HashMap::from([("nick_name3".to_string(), "nick_name3".to_string())]),
-            rf.translate_to_real_devices(vec!["nick_name3".to_string()].as_ref())
+            rf.translate_to_real_devices(vec!["nick_name3".to_string()].as_ref()).unwrap()
         );
 
         rf.remove_user_info(vec![&"nick_name3".to_string()]);
@@ -166,6 +334,99 @@ mod tests {
         write_runfile_to_file(tmp_path().to_str().unwrap(), &rf).unwrap();
     }
 
+    #[test]
+    fn test_write_atomic() {
+        let path: PathBuf = std::env::temp_dir().join("bark_write_atomic_test").join("bark.conf");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+
+        write_atomic(&path, "old").unwrap();
+        write_atomic(&path, "new").unwrap();
+        assert_eq!("new", std::fs::read_to_string(&path).unwrap());
+        // no temp file is left behind
+        assert_eq!(1, std::fs::read_dir(path.parent().unwrap()).unwrap().count());
+
+        let rf = RunFile::new_for_test();
+        write_runfile_to_file(path.to_str().unwrap(), &rf).unwrap();
+        assert_eq!("new", std::fs::read_to_string(sibling(&path, "bak")).unwrap());
+        assert_eq!(rf.to_string().unwrap(), std::fs::read_to_string(&path).unwrap());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir: PathBuf = std::env::temp_dir().join("bark_permissions_test");
+        let path: PathBuf = dir.join("bark").join("bark.conf");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let token_cache: PathBuf = dir.join("cache").join("token.toml");
+        let token_cache_path: &str = token_cache.to_str().unwrap();
+
+        // created for the owner only
+        write_runfile_to_file(path.to_str().unwrap(), &RunFile::new_for_test()).unwrap();
+        assert_eq!(None, shared_mode(&path));
+        assert_eq!(None, shared_mode(path.parent().unwrap()));
+        write_token_cache_to_file(token_cache_path, &read_token_cache_from_file(token_cache_path)).unwrap();
+        assert_eq!(None, shared_mode(&token_cache));
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::set_permissions(path.parent().unwrap(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::set_permissions(&token_cache, std::fs::Permissions::from_mode(0o644)).unwrap();
+        // a rewrite keeps the mode the file has
+        write_runfile_to_file(path.to_str().unwrap(), &RunFile::new_for_test()).unwrap();
+        assert_eq!(Some(0o644), shared_mode(&path));
+
+        assert_eq!(
+            vec![
+                format!("{}.bak: 0644 -> 0600", path.display()),
+                format!("{}: 0644 -> 0600", path.display()),
+                format!("{}: 0644 -> 0600", token_cache.display()),
+                format!("{}: 0755 -> 0700", path.parent().unwrap().display()),
+            ],
+            fix_permissions(path.to_str().unwrap(), token_cache_path).unwrap()
+        );
+        assert_eq!(None, shared_mode(&path));
+        assert!(fix_permissions(path.to_str().unwrap(), token_cache_path).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let path: PathBuf = std::env::temp_dir().join("bark_lock_file_test.conf");
+        let lock: File = lock_file(&path).unwrap();
+        let other: File = File::open(sibling(&path, "lock")).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+    }
+
+    #[test]
+    fn test_lock_queue() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("bark_lock_queue_test_{}", std::process::id()));
+        let path: String = dir.join("bark.queue").to_string_lossy().to_string();
+        let options: CMD = CMD::try_parse_from(["bark", "-r", "alice", "-m", "hi", "--delay", "60"]).unwrap();
+
+        // adds racing each other all get their own id and none is lost
+        let adds: Vec<std::thread::JoinHandle<u64>> = (0..8).map(|_| {
+            let (path, options) = (path.clone(), options.clone());
+            std::thread::spawn(move || {
+                let (_lock, mut queue) = lock_queue(&path).unwrap();
+                let id: u64 = queue.add(Local::now(), None, CatchUp::Skip, options, Local::now());
+                queue.save().unwrap();
+                id
+            })
+        }).collect();
+        let mut ids: Vec<u64> = adds.into_iter().map(|add| add.join().unwrap()).collect();
+        ids.sort();
+        assert_eq!((1..=8).collect::<Vec<u64>>(), ids);
+        assert_eq!(8, read_queue_from_file(&path).unwrap().due(Local::now()).len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     #[cfg(not(windows))]
     fn test_resolve_tilde_home() {
@@ -0,0 +1,104 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use std::io::Read;
+
+/// APNs rejects payloads larger than 4KB
+pub const MAX_PAYLOAD_SIZE: usize = 4096;
+
+/// appended to a msg body cut to fit into the payload
+pub const TRUNCATED_MARKER: &str = "…(truncated)";
+
+/// read the whole msg content from stdin
+pub fn read_msg_from_stdin() -> Result<String, std::io::Error> {
+    let mut content: String = String::new();
+    std::io::stdin().read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// read the whole msg content from a file
+pub fn read_msg_from_file(path: &str) -> Result<String, std::io::Error> {
+    std::fs::read_to_string(path)
+}
+
+/// escape `str` to embed it between the double quotes of a json string
+///
+/// `bark_dev::msg::Msg` writes the fields into the payload as they are
+pub fn escape_json(str: &str) -> String {
+    let mut escaped: String = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// cut `str` to at most `max_len` bytes, [`TRUNCATED_MARKER`] included
+///
+/// the cut never splits a char, `str` is returned as is if it fits
+pub fn truncate(str: &str, max_len: usize) -> String {
+    if str.len() <= max_len {
+        return str.to_string();
+    }
+    let mut end: usize = max_len.saturating_sub(TRUNCATED_MARKER.len());
+    while !str.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", str[..end].trim_end(), TRUNCATED_MARKER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_json() {
+        assert_eq!("hello world", escape_json("hello world"));
+        assert_eq!("say \\\"hi\\\"\\nC:\\\\bark\\tok", escape_json("say \"hi\"\nC:\\bark\tok"));
+        assert_eq!("bell\\u0007", escape_json("bell\u{7}"));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!("short", truncate("short", 100));
+
+        let long: String = "a".repeat(100);
+        let r: String = truncate(&long, 50);
+        assert_eq!(50, r.len());
+        assert!(r.ends_with(TRUNCATED_MARKER));
+
+        // never split a multi-byte char
+        let long: String = "你好".repeat(20);
+        let r: String = truncate(&long, 30);
+        assert!(r.len() <= 30);
+        assert!(r.starts_with("你好"));
+        assert!(r.ends_with(TRUNCATED_MARKER));
+    }
+}
@@ -0,0 +1,112 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use chrono::Local;
+
+/// replace `{{name}}` placeholders by what `lookup` finds for the name, spaces around the name are ignored
+///
+/// every placeholder not found is reported, so a msg is never sent half rendered
+pub fn render(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut rendered: String = String::with_capacity(text.len());
+    let mut unresolved: Vec<String> = Vec::new();
+    let mut rest: &str = text;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start + 2..].find("}}") else {
+            return Err(format!("unclosed placeholder in \"{}\"", text));
+        };
+        let name: &str = rest[start + 2..start + 2 + end].trim();
+        match lookup(name) {
+            Some(value) => rendered.push_str(&value),
+            None => {
+                if !unresolved.iter().any(|u| u == name) {
+                    unresolved.push(name.to_string());
+                }
+            },
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    rendered.push_str(rest);
+
+    if unresolved.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(format!("unresolved placeholder: {}, pass it by --var", unresolved.join(", ")))
+    }
+}
+
+/// variables every template can use: hostname, now, date, user and env.NAME for environment variables
+pub fn builtin_var(name: &str) -> Option<String> {
+    match name {
+        "hostname" => hostname(),
+        "now" => Some(Local::now().format("%Y-%m-%d %H:%M:%S").to_string()),
+        "date" => Some(Local::now().format("%Y-%m-%d").to_string()),
+        "user" => std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok().or_else(|| command_output("whoami")),
+        _ => name.strip_prefix("env.").and_then(|key| std::env::var(key).ok()),
+    }
+}
+
+fn hostname() -> Option<String> {
+    if let Ok(name) = std::env::var("COMPUTERNAME") {
+        return Some(name);
+    }
+    command_output("hostname")
+}
+
+fn command_output(program: &str) -> Option<String> {
+    std::process::Command::new(program).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|output| !output.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(name: &str) -> Option<String> {
+        match name {
+            "env" => Some("prod".to_string()),
+            "version" => Some("1.2".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(Ok("deploy 1.2 to prod".to_string()), render("deploy {{version}} to {{ env }}", vars));
+        assert_eq!(Ok("no placeholder { here }".to_string()), render("no placeholder { here }", vars));
+        assert_eq!(
+            Err("unresolved placeholder: a, b, pass it by --var".to_string()),
+            render("{{a}} {{env}} {{b}} {{a}}", vars)
+        );
+        assert!(render("deploy {{version", vars).is_err());
+    }
+
+    #[test]
+    fn test_builtin_var() {
+        assert!(builtin_var("now").is_some());
+        assert_eq!(std::env::var("PATH").ok(), builtin_var("env.PATH"));
+        assert_eq!(None, builtin_var("env.BARK_SURELY_NOT_SET"));
+        assert_eq!(None, builtin_var("version"));
+    }
+}
@@ -0,0 +1,183 @@
+// MIT License
+//
+// Copyright (c) 2024 66f94eae
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+use std::time::Duration;
+
+use chrono::{DateTime, Days, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+/// absolute formats accepted by `parse_time` besides RFC3339, interpreted in the local timezone
+const DATE_TIME_FORMATS: [&str; 6] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+];
+
+/// human readable duration like "1h 2m 3s", sub second durations keep one decimal like "0.4s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs: u64 = duration.as_secs();
+    if secs < 60 {
+        return format!("{:.1}s", duration.as_secs_f64());
+    }
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    } else {
+        format!("{}m {}s", m, s)
+    }
+}
+
+/// parse a point in time relative to `now`, accepted forms are
+/// - RFC3339 like "2026-10-19T09:00:00+08:00"
+/// - local date and time like "2026-10-19 09:00" or "2026/10/19 09:00:30"
+/// - time of day with an optional "today"/"tomorrow" like "18:30", "9am", "tomorrow 9:30pm"
+///
+/// local times falling into a daylight saving overlap resolve to the earlier instant,
+/// local times skipped by a daylight saving gap are rejected
+pub fn parse_time<Tz: TimeZone>(input: &str, now: &DateTime<Tz>) -> Result<DateTime<Tz>, String> {
+    let input: String = input.trim().to_lowercase();
+    if input.is_empty() {
+        return Err("empty time".to_string());
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(&input.to_uppercase()) {
+        return Ok(dt.with_timezone(&now.timezone()));
+    }
+    for format in DATE_TIME_FORMATS {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(&input, format) {
+            return to_local(&now.timezone(), naive);
+        }
+    }
+
+    let today: NaiveDate = now.date_naive();
+    let (date, time) = match input.split_once(char::is_whitespace) {
+        Some(("today", time)) => (today, time.trim()),
+        Some(("tomorrow", time)) => (today.checked_add_days(Days::new(1)).ok_or("date out of range")?, time.trim()),
+        _ => (today, input.as_str()),
+    };
+    match parse_time_of_day(time) {
+        Some(time) => to_local(&now.timezone(), date.and_time(time)),
+        None => Err(format!("unrecognized time: {}", input)),
+    }
+}
+
+/// "9", "09:30", "21:00:15", "9am", "9:30 pm", "noon", "midnight"
+fn parse_time_of_day(input: &str) -> Option<NaiveTime> {
+    match input {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+    let (clock, meridiem) = if let Some(clock) = input.strip_suffix("am") {
+        (clock.trim_end(), Some(0))
+    } else if let Some(clock) = input.strip_suffix("pm") {
+        (clock.trim_end(), Some(12))
+    } else {
+        (input, None)
+    };
+
+    let parts: Vec<&str> = clock.split(':').collect();
+    if parts.len() > 3 || parts.iter().any(|p| p.is_empty() || p.len() > 2 || !p.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    // a bare number is only a time with am/pm, "9" alone is too ambiguous
+    if parts.len() == 1 && meridiem.is_none() {
+        return None;
+    }
+    let nums: Vec<u32> = parts.iter().map(|p| p.parse().unwrap()).collect();
+    let mut hour: u32 = nums[0];
+    if let Some(offset) = meridiem {
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+        hour = hour % 12 + offset;
+    }
+    NaiveTime::from_hms_opt(hour, *nums.get(1).unwrap_or(&0), *nums.get(2).unwrap_or(&0))
+}
+
+fn to_local<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> Result<DateTime<Tz>, String> {
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(dt) => Ok(dt),
+        LocalResult::Ambiguous(earliest, _) => Ok(earliest),
+        LocalResult::None => Err(format!(
+            "{} does not exist in the local timezone, it is skipped by a daylight saving time change",
+            naive.format("%Y-%m-%d %H:%M:%S")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!("0.4s", format_duration(Duration::from_millis(400)));
+        assert_eq!("59.0s", format_duration(Duration::from_secs(59)));
+        assert_eq!("1m 0s", format_duration(Duration::from_secs(60)));
+        assert_eq!("2h 0m 5s", format_duration(Duration::from_secs(7205)));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        use chrono::{FixedOffset, Timelike};
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let now = tz.with_ymd_and_hms(2026, 10, 18, 10, 0, 0).unwrap();
+        let at = |input: &str| parse_time(input, &now).map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string());
+
+        assert_eq!(Ok("2026-10-19 09:00:00".to_string()), at("2026-10-19 09:00"));
+        assert_eq!(Ok("2026-10-19 09:00:30".to_string()), at("2026/10/19 09:00:30"));
+        assert_eq!(Ok("2026-10-19 09:00:00".to_string()), at("2026-10-19T01:00:00Z"));
+        assert_eq!(Ok("2026-10-18 18:30:00".to_string()), at("18:30"));
+        assert_eq!(Ok("2026-10-18 21:30:00".to_string()), at("today 9:30 PM"));
+        assert_eq!(Ok("2026-10-19 09:00:00".to_string()), at("tomorrow 9am"));
+        assert_eq!(Ok("2026-10-19 00:00:00".to_string()), at("tomorrow 12am"));
+        assert_eq!(Ok("2026-10-19 12:00:00".to_string()), at("tomorrow noon"));
+        assert_eq!(0, parse_time("midnight", &now).unwrap().hour());
+        assert!(at("9").is_err());
+        assert!(at("13pm").is_err());
+        assert!(at("25:00").is_err());
+        assert!(at("next week").is_err());
+        assert!(at("").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_dst() {
+        use chrono_tz::America::New_York;
+        let now = New_York.with_ymd_and_hms(2026, 3, 7, 12, 0, 0).unwrap();
+        // 2026-03-08 02:00 jumps to 03:00
+        assert!(parse_time("tomorrow 2:30am", &now).unwrap_err().contains("daylight saving"));
+        assert_eq!(
+            "2026-03-08T03:30:00-04:00",
+            parse_time("tomorrow 3:30am", &now).unwrap().to_rfc3339()
+        );
+
+        let now = New_York.with_ymd_and_hms(2026, 10, 31, 12, 0, 0).unwrap();
+        // 2026-11-01 01:00-02:00 happens twice, the first one wins
+        assert_eq!(
+            "2026-11-01T01:30:00-04:00",
+            parse_time("tomorrow 1:30am", &now).unwrap().to_rfc3339()
+        );
+    }
+}